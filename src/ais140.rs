@@ -0,0 +1,183 @@
+use crate::config::{CoordinateFormat, UtcOffset};
+use crate::error::RsmParseError;
+use crate::rsm::{self, Fields, Hemisphere};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// Minimum number of fields in a login packet (up to and including longitude direction).
+pub const LOGIN_MIN_FIELDS: usize = 13;
+
+/// Minimum number of fields in a health packet (up to and including digital I/O status).
+pub const HEALTH_MIN_FIELDS: usize = 14;
+
+/// Minimum number of fields in an emergency packet (up to and including location provider).
+pub const EMERGENCY_MIN_FIELDS: usize = 18;
+
+/// AIS-140 login packet, sent once when a device connects.
+///
+/// Shares the RSM header fields (0-7) with tracking packets, followed by the
+/// protocol version and the last known position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginPacket {
+    pub header: String,
+    pub vendor_id: String,
+    pub firmware_version: String,
+    pub packet_type: String,
+    pub packet_status: String,
+    pub imei: String,
+    pub vehicle_reg_no: String,
+    pub protocol_version: String,
+    pub latitude: f64,
+    pub latitude_dir: Hemisphere,
+    pub longitude: f64,
+    pub longitude_dir: Hemisphere,
+}
+
+impl LoginPacket {
+    pub fn parse(message: &str) -> Result<Self, RsmParseError> {
+        let fields = Fields::split(message, LOGIN_MIN_FIELDS)?;
+
+        Ok(Self {
+            header: fields.text(0),
+            vendor_id: fields.text(1),
+            firmware_version: fields.text(2),
+            packet_type: fields.text(3),
+            packet_status: fields.text(5),
+            imei: fields.imei(6)?,
+            vehicle_reg_no: fields.text(7),
+            protocol_version: fields.text(8),
+            latitude: fields.required(9, "latitude", rsm::parse_f64)?,
+            latitude_dir: fields.required(10, "latitude_dir", rsm::parse_latitude_dir)?,
+            longitude: fields.required(11, "longitude", rsm::parse_f64)?,
+            longitude_dir: fields.required(12, "longitude_dir", rsm::parse_longitude_dir)?,
+        })
+    }
+
+    pub fn position(&self, format: CoordinateFormat) -> Result<(f64, f64), RsmParseError> {
+        rsm::position(format, (self.latitude, self.latitude_dir), (self.longitude, self.longitude_dir))
+    }
+}
+
+/// AIS-140 health monitoring packet.
+///
+/// Shares the RSM header fields (0-7) with tracking packets, followed by battery,
+/// memory, reporting interval and I/O status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthPacket {
+    pub header: String,
+    pub vendor_id: String,
+    pub firmware_version: String,
+    pub packet_type: String,
+    pub packet_status: String,
+    pub imei: String,
+    pub vehicle_reg_no: String,
+    pub battery_percentage: u8,
+    pub low_battery_threshold: u8,
+    pub memory_percentage: u8,
+    /// Reporting interval in seconds while the ignition is on.
+    pub data_update_rate_ignition_on: u32,
+    /// Reporting interval in seconds while the ignition is off.
+    pub data_update_rate_ignition_off: u32,
+    pub digital_io_status: String,
+    pub analog_io_status: Option<String>,
+}
+
+impl HealthPacket {
+    pub fn parse(message: &str) -> Result<Self, RsmParseError> {
+        let fields = Fields::split(message, HEALTH_MIN_FIELDS)?;
+
+        Ok(Self {
+            header: fields.text(0),
+            vendor_id: fields.text(1),
+            firmware_version: fields.text(2),
+            packet_type: fields.text(3),
+            packet_status: fields.text(5),
+            imei: fields.imei(6)?,
+            vehicle_reg_no: fields.text(7),
+            battery_percentage: fields.required(8, "battery_percentage", parse_percentage)?,
+            low_battery_threshold: fields.required(9, "low_battery_threshold", parse_percentage)?,
+            memory_percentage: fields.required(10, "memory_percentage", parse_percentage)?,
+            data_update_rate_ignition_on: fields.required(11, "data_update_rate_ignition_on", |v| v.parse().ok())?,
+            data_update_rate_ignition_off: fields.required(12, "data_update_rate_ignition_off", |v| v.parse().ok())?,
+            digital_io_status: fields.required(13, "digital_io_status", parse_io_status)?,
+            analog_io_status: fields.optional(14, "analog_io_status", |v| Some(v.to_string()))?,
+        })
+    }
+}
+
+/// AIS-140 emergency (EPB) packet, raised by the panic button.
+///
+/// Shares the RSM header fields (0-7) with tracking packets, followed by the
+/// position fix, distance travelled, location provider and reply number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyPacket {
+    pub header: String,
+    pub vendor_id: String,
+    pub firmware_version: String,
+    pub packet_type: String,
+    pub packet_status: String,
+    pub imei: String,
+    pub vehicle_reg_no: String,
+    pub gps_fix: bool,
+    /// Device-local timestamp; see [`EmergencyPacket::gps_time`] for the UTC value.
+    pub date_time: NaiveDateTime,
+    pub latitude: f64,
+    pub latitude_dir: Hemisphere,
+    pub longitude: f64,
+    pub longitude_dir: Hemisphere,
+    pub altitude: f64,
+    pub speed: f64,
+    /// Distance travelled in kilometres.
+    pub distance: f64,
+    /// `G` for a GPS position, `N` for a network (cell) position.
+    pub location_provider: String,
+    /// Phone number the control room can call back on.
+    pub reply_number: Option<String>,
+}
+
+impl EmergencyPacket {
+    pub fn parse(message: &str) -> Result<Self, RsmParseError> {
+        let fields = Fields::split(message, EMERGENCY_MIN_FIELDS)?;
+
+        Ok(Self {
+            header: fields.text(0),
+            vendor_id: fields.text(1),
+            firmware_version: fields.text(2),
+            packet_type: fields.text(3),
+            packet_status: fields.text(5),
+            imei: fields.imei(6)?,
+            vehicle_reg_no: fields.text(7),
+            gps_fix: fields.required(8, "gps_fix", rsm::parse_gps_fix)?,
+            date_time: fields.date_time(9)?,
+            latitude: fields.required(10, "latitude", rsm::parse_f64)?,
+            latitude_dir: fields.required(11, "latitude_dir", rsm::parse_latitude_dir)?,
+            longitude: fields.required(12, "longitude", rsm::parse_f64)?,
+            longitude_dir: fields.required(13, "longitude_dir", rsm::parse_longitude_dir)?,
+            altitude: fields.required(14, "altitude", rsm::parse_f64)?,
+            speed: fields.required(15, "speed", rsm::parse_f64)?,
+            distance: fields.required(16, "distance", rsm::parse_f64)?,
+            location_provider: fields.required(17, "location_provider", parse_location_provider)?,
+            reply_number: fields.optional(18, "reply_number", |v| Some(v.to_string()))?,
+        })
+    }
+
+    /// GPS time in UTC, interpreting `date_time` in the device's configured offset.
+    pub fn gps_time(&self, timezone: UtcOffset) -> DateTime<Utc> {
+        (self.date_time - timezone.0).and_utc()
+    }
+
+    pub fn position(&self, format: CoordinateFormat) -> Result<(f64, f64), RsmParseError> {
+        rsm::position(format, (self.latitude, self.latitude_dir), (self.longitude, self.longitude_dir))
+    }
+}
+
+fn parse_percentage(value: &str) -> Option<u8> {
+    value.parse().ok().filter(|v| *v <= 100)
+}
+
+fn parse_io_status(value: &str) -> Option<String> {
+    value.chars().all(|c| c == '0' || c == '1').then(|| value.to_string())
+}
+
+fn parse_location_provider(value: &str) -> Option<String> {
+    matches!(value, "G" | "N").then(|| value.to_string())
+}
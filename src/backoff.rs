@@ -0,0 +1,45 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with optional jitter, shared by RabbitMQ reconnection and
+/// Kafka produce retries.
+pub struct Backoff {
+    initial_backoff_ms: u64,
+    max_backoff_ms: u64,
+    multiplier: f64,
+    /// Fraction of each delay that is randomised, between 0.0 and 1.0.
+    jitter: f64,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial_backoff_ms: u64, max_backoff_ms: u64, multiplier: f64, jitter: f64) -> Self {
+        Self { initial_backoff_ms, max_backoff_ms, multiplier, jitter, attempt: 0 }
+    }
+
+    /// Number of delays handed out since the last reset.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let exponent = self.attempt.min(32) as i32;
+        self.attempt = self.attempt.saturating_add(1);
+
+        let base = (self.initial_backoff_ms as f64 * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_backoff_ms as f64);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::rng().random_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+
+        Duration::from_millis((base * factor) as u64)
+    }
+}
@@ -1,24 +1,615 @@
+use chrono::FixedOffset;
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
 #[derive(Debug, Deserialize)]
 pub struct RabbitMQConfig {
     pub uri: String,
     pub queue: String,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub tls: RabbitMQTlsConfig,
+    /// SASL mechanism used to log in, overriding the URI's `auth_mechanism` parameter.
+    pub auth_mechanism: Option<AmqpAuthMechanism>,
+    /// Unacknowledged deliveries RabbitMQ may push at once (0 for no limit); defaults
+    /// to a multiple of the processor workers.
+    pub prefetch_count: Option<u16>,
+    /// Consumer tag, also used as the connection name; defaults to one derived from `instance_id`.
+    pub consumer_tag: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// TLS settings used with `amqps://` URIs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RabbitMQTlsConfig {
+    /// PEM bundle of CAs trusted to sign the server certificate, on top of the system store.
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate and private key, required for EXTERNAL authentication.
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    /// Name sent as SNI and verified against the server certificate; defaults to the URI host.
+    pub server_name: Option<String>,
+}
+
+impl RabbitMQTlsConfig {
+    pub fn is_configured(&self) -> bool {
+        self.ca_file.is_some() || self.cert_file.is_some() || self.key_file.is_some() || self.server_name.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmqpAuthMechanism {
+    /// Username and password from the URI.
+    Plain,
+    /// Authenticate with the TLS client certificate.
+    External,
+}
+
+/// Exponential backoff applied between RabbitMQ reconnection attempts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomised, between 0.0 and 1.0.
+    pub jitter: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct KafkaConfig {
     pub brokers: String,
     pub valid_topic: String,
     pub invalid_topic: String,
+    #[serde(default)]
+    pub topics: TopicsConfig,
+    /// Time allowed for a single produce attempt, including waiting for a full local queue.
+    #[serde(default = "default_send_timeout_ms")]
+    pub send_timeout_ms: u64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// librdkafka producer properties passed straight through, e.g. `acks` or
+    /// `linger.ms`. Underscores in keys stand for dots, so `linger_ms` also works.
+    #[serde(default)]
+    pub producer: HashMap<String, String>,
+    #[serde(default)]
+    pub security: KafkaSecurityConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    #[default]
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    fn name(self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "plaintext",
+            SecurityProtocol::Ssl => "ssl",
+            SecurityProtocol::SaslPlaintext => "sasl_plaintext",
+            SecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+
+    fn uses_sasl(self) -> bool {
+        matches!(self, SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl)
+    }
+
+    fn uses_tls(self) -> bool {
+        matches!(self, SecurityProtocol::Ssl | SecurityProtocol::SaslSsl)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    fn name(self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+/// Kafka authentication and encryption. Credentials can be given inline or read
+/// from files, such as mounted secrets; files win when both are set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KafkaSecurityConfig {
+    pub protocol: SecurityProtocol,
+    pub sasl_mechanism: Option<SaslMechanism>,
+    pub username: Option<String>,
+    pub username_file: Option<PathBuf>,
+    pub password: Option<String>,
+    pub password_file: Option<PathBuf>,
+    /// PEM bundle of CAs trusted to sign the broker certificates; the system store is used when unset.
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate and private key for mutual TLS.
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub key_password: Option<String>,
+}
+
+impl KafkaSecurityConfig {
+    /// Check the settings and credential files, and turn them into librdkafka properties.
+    pub fn client_properties(&self) -> Result<Vec<(&'static str, String)>, ConfigError> {
+        let protocol = self.protocol;
+        let mut properties = vec![("security.protocol", protocol.name().to_string())];
+
+        if protocol.uses_sasl() {
+            let mechanism = self.sasl_mechanism.ok_or_else(|| {
+                invalid(format!("kafka.security.sasl_mechanism is required with {}", protocol.name()))
+            })?;
+            let username = secret(self.username.as_deref(), self.username_file.as_deref(), "username")?;
+            let password = secret(self.password.as_deref(), self.password_file.as_deref(), "password")?;
+            properties.push(("sasl.mechanism", mechanism.name().to_string()));
+            properties.push(("sasl.username", username));
+            properties.push(("sasl.password", password));
+        } else if self.sasl_mechanism.is_some() {
+            return Err(invalid(format!("kafka.security.sasl_mechanism has no effect with {}", protocol.name())));
+        }
+
+        let tls_files = [&self.ca_file, &self.cert_file, &self.key_file];
+        if !protocol.uses_tls() {
+            if tls_files.iter().any(|file| file.is_some()) {
+                return Err(invalid(format!("kafka.security TLS files have no effect with {}", protocol.name())));
+            }
+            return Ok(properties);
+        }
+
+        if self.cert_file.is_some() != self.key_file.is_some() {
+            return Err(invalid("kafka.security.cert_file and key_file must be set together".to_string()));
+        }
+
+        for (property, file) in [
+            ("ssl.ca.location", &self.ca_file),
+            ("ssl.certificate.location", &self.cert_file),
+            ("ssl.key.location", &self.key_file),
+        ] {
+            if let Some(file) = file {
+                readable(file)?;
+                properties.push((property, file.display().to_string()));
+            }
+        }
+
+        if let Some(key_password) = &self.key_password {
+            properties.push(("ssl.key.password", key_password.clone()));
+        }
+
+        Ok(properties)
+    }
+}
+
+/// A credential given inline or in a file, with any trailing newline removed.
+fn secret(value: Option<&str>, file: Option<&Path>, name: &str) -> Result<String, ConfigError> {
+    let value = match file {
+        Some(file) => fs::read_to_string(file)
+            .map_err(|e| invalid(format!("cannot read kafka.security.{}_file {}: {}", name, file.display(), e)))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        None => value.unwrap_or_default().to_string(),
+    };
+
+    if value.is_empty() {
+        return Err(invalid(format!("kafka.security.{} or {}_file is required with SASL", name, name)));
+    }
+    Ok(value)
+}
+
+fn readable(file: &Path) -> Result<(), ConfigError> {
+    fs::File::open(file)
+        .map(drop)
+        .map_err(|e| invalid(format!("cannot read {}: {}", file.display(), e)))
+}
+
+/// Configuration error for a setting that is present but unusable.
+pub(crate) fn invalid(message: String) -> ConfigError {
+    ConfigError::Message(message)
+}
+
+fn default_send_timeout_ms() -> u64 {
+    5000
+}
+
+/// Exponential backoff applied between attempts of a Kafka write that failed with a
+/// transient error, such as a full local queue or an unavailable broker.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Attempts in total, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomised, between 0.0 and 1.0.
+    pub jitter: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 5000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl KafkaConfig {
+    /// Topic that decoded records of the given kind are published to.
+    pub fn topic(&self, kind: PacketKind) -> &str {
+        let topic = match kind {
+            PacketKind::Tracking => None,
+            PacketKind::Login => self.topics.login.as_deref(),
+            PacketKind::Health => self.topics.health.as_deref(),
+            PacketKind::Emergency => self.topics.emergency.as_deref(),
+        };
+        topic.unwrap_or(&self.valid_topic)
+    }
+}
+
+/// Per packet kind topics; kinds without a topic go to `valid_topic`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TopicsConfig {
+    pub login: Option<String>,
+    pub health: Option<String>,
+    pub emergency: Option<String>,
+}
+
+/// Kind of record a packet carries. Each kind has its own output schema and topic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketKind {
+    /// Periodic position report.
+    #[default]
+    Tracking,
+    /// Sent once when a device connects.
+    Login,
+    /// Device health monitoring report.
+    Health,
+    /// Emergency (panic button) alert.
+    Emergency,
+}
+
+impl PacketKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketKind::Tracking => "tracking",
+            PacketKind::Login => "login",
+            PacketKind::Health => "health",
+            PacketKind::Emergency => "emergency",
+        }
+    }
+}
+
+/// When a RabbitMQ delivery is acknowledged relative to the Kafka write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckMode {
+    /// Ack as soon as the delivery is received; a failed Kafka write loses the message.
+    AtMostOnce,
+    /// Ack only after Kafka confirms the write; failed writes are nacked.
+    AtLeastOnce,
+}
+
+/// How the Kafka record key is derived from a message.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum KeyStrategy {
+    /// Device IMEI, so each vehicle's records land on a single partition.
+    #[default]
+    Imei,
+    VehicleRegNo,
+    /// No key; records are spread across partitions by the producer.
+    None,
+    /// Value at a JSON pointer, resolved against the output JSON (or the raw payload
+    /// when it is JSON but could not be parsed as a tracking packet).
+    JsonPointer { pointer: String },
+}
+
+/// How a device encodes latitude and longitude values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateFormat {
+    /// Plain decimal degrees, e.g. `28.623921`.
+    #[default]
+    DecimalDegrees,
+    /// NMEA-style degrees and decimal minutes, e.g. `2837.4353` (`ddmm.mmmm` / `dddmm.mmmm`).
+    #[serde(alias = "ddmm")]
+    DegreesMinutes,
+}
+
+/// How the checksum field that ends an RSM packet (just before the `*` terminator) is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RsmChecksum {
+    /// Packets carry no checksum field.
+    #[default]
+    None,
+    /// Packets end with a checksum field, which is dropped without being verified.
+    Skip,
+    /// XOR of every byte after `$` up to the comma before the checksum, as two hex digits.
+    Xor,
+    /// CRC-16/CCITT-FALSE over the same bytes, as four hex digits.
+    Crc16,
+}
+
+/// Fixed UTC offset written as `UTC`, `Z` or `±hh:mm` (e.g. `+05:30`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UtcOffset(pub FixedOffset);
+
+impl Default for UtcOffset {
+    fn default() -> Self {
+        Self(FixedOffset::east_opt(0).expect("zero offset is valid"))
+    }
+}
+
+impl TryFrom<String> for UtcOffset {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case("utc") || value == "Z" {
+            return Ok(Self::default());
+        }
+        value
+            .parse::<FixedOffset>()
+            .map(Self)
+            .map_err(|_| format!("invalid UTC offset '{}', expected UTC or ±hh:mm", value))
+    }
+}
+
+/// Decoding overrides for a single vendor or device.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RsmProfile {
+    pub coordinate_format: Option<CoordinateFormat>,
+    pub timezone: Option<UtcOffset>,
+    pub checksum: Option<RsmChecksum>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RsmConfig {
+    pub coordinate_format: CoordinateFormat,
+    /// Offset of the device clock used to interpret `date_time`.
+    pub timezone: UtcOffset,
+    /// How the checksum field before the `*` terminator is handled.
+    pub checksum: RsmChecksum,
+    /// GPS times further ahead of the bridge clock than this are flagged as suspect.
+    pub max_future_skew_secs: u64,
+    /// GPS times older than this are flagged as suspect.
+    pub max_past_age_secs: u64,
+    /// Overrides keyed by vendor ID (second RSM field).
+    pub vendors: HashMap<String, RsmProfile>,
+    /// Overrides keyed by IMEI; these take precedence over vendor overrides.
+    pub devices: HashMap<String, RsmProfile>,
+    /// Packet type codes (fourth RSM field) mapped to a packet kind, on top of the
+    /// built-in AIS-140 codes.
+    pub packet_types: HashMap<String, PacketKind>,
+}
+
+impl Default for RsmConfig {
+    fn default() -> Self {
+        Self {
+            coordinate_format: CoordinateFormat::default(),
+            timezone: UtcOffset::default(),
+            checksum: RsmChecksum::default(),
+            max_future_skew_secs: 300,
+            max_past_age_secs: 7 * 24 * 60 * 60,
+            vendors: HashMap::new(),
+            devices: HashMap::new(),
+            packet_types: HashMap::new(),
+        }
+    }
+}
+
+impl RsmConfig {
+    pub fn coordinate_format(&self, vendor_id: &str, imei: &str) -> CoordinateFormat {
+        self.profiles(vendor_id, imei)
+            .find_map(|profile| profile.coordinate_format)
+            .unwrap_or(self.coordinate_format)
+    }
+
+    pub fn timezone(&self, vendor_id: &str, imei: &str) -> UtcOffset {
+        self.profiles(vendor_id, imei)
+            .find_map(|profile| profile.timezone)
+            .unwrap_or(self.timezone)
+    }
+
+    pub fn checksum(&self, vendor_id: &str, imei: &str) -> RsmChecksum {
+        self.profiles(vendor_id, imei)
+            .find_map(|profile| profile.checksum)
+            .unwrap_or(self.checksum)
+    }
+
+    /// Kind of packet identified by a packet type code. Unknown codes are treated
+    /// as tracking packets.
+    pub fn packet_kind(&self, packet_type: &str) -> PacketKind {
+        let configured = self
+            .packet_types
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(packet_type))
+            .map(|(_, kind)| *kind);
+
+        configured.unwrap_or(match packet_type.to_ascii_uppercase().as_str() {
+            "LGN" | "LOGIN" => PacketKind::Login,
+            "HLM" | "HEALTH" => PacketKind::Health,
+            "EPB" => PacketKind::Emergency,
+            _ => PacketKind::Tracking,
+        })
+    }
+
+    /// Matching profiles, most specific first. Keys are compared case-insensitively
+    /// because environment variable overrides are lowercased.
+    fn profiles<'a>(&'a self, vendor_id: &'a str, imei: &'a str) -> impl Iterator<Item = &'a RsmProfile> {
+        let lookup = |profiles: &'a HashMap<String, RsmProfile>, id: &'a str| {
+            profiles
+                .iter()
+                .find(move |(key, _)| key.eq_ignore_ascii_case(id))
+                .map(|(_, profile)| profile)
+        };
+
+        lookup(&self.devices, imei).into_iter().chain(lookup(&self.vendors, vendor_id))
+    }
+}
+
+/// Target JSON type of an output field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+/// Unit conversion applied to a numeric source value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitConversion {
+    KmhToMph,
+    MphToKmh,
+    KmhToKnots,
+    KnotsToKmh,
+    MetersToFeet,
+    MillivoltsToVolts,
+    VoltsToMillivolts,
+}
+
+/// One field of the output JSON and where its value comes from.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FieldMapping {
+    /// Output field name.
+    pub name: String,
+    /// Canonical source field, e.g. `imei`, `gps_time` or `speed`.
+    pub source: String,
+    /// Coerce the value to this type; the source type is kept when unset.
+    #[serde(default, rename = "type")]
+    pub field_type: Option<FieldType>,
+    #[serde(default)]
+    pub unit: Option<UnitConversion>,
+    /// Value used when the source field is missing or null.
+    #[serde(default)]
+    pub default: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Tracking output schema; the built-in vehicle tracking schema is used when empty.
+    pub fields: Vec<FieldMapping>,
+    /// Login output schema; the built-in login schema is used when empty.
+    pub login: Vec<FieldMapping>,
+    /// Health output schema; the built-in health schema is used when empty.
+    pub health: Vec<FieldMapping>,
+    /// Emergency output schema; the built-in emergency schema is used when empty.
+    pub emergency: Vec<FieldMapping>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ProcessorConfig {
+    pub ack_mode: AckMode,
+    /// Number of messages processed concurrently; ordering is kept per device.
+    pub workers: usize,
+    pub key: KeyStrategy,
+    /// AMQP header naming the payload's protocol; payloads without it are identified by content.
+    pub protocol_header: String,
+    /// AMQP header carrying the device ID for protocols whose packets do not include one.
+    pub device_id_header: String,
+    pub rsm: RsmConfig,
+    pub output: OutputConfig,
+    /// Requeue nacked deliveries; when false they go to the queue's dead-letter exchange.
+    pub requeue_on_failure: bool,
+    /// Time allowed on shutdown to drain in-flight messages and flush Kafka.
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        Self {
+            ack_mode: AckMode::AtLeastOnce,
+            workers: 4,
+            key: KeyStrategy::default(),
+            protocol_header: "x-protocol".to_string(),
+            device_id_header: "x-device-id".to_string(),
+            rsm: RsmConfig::default(),
+            output: OutputConfig::default(),
+            requeue_on_failure: true,
+            shutdown_timeout_secs: 20,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Address the metrics and health endpoints listen on.
+    pub address: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0:9090".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
+    /// Identifies this bridge instance; defaults to the host name, or a random ID.
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,
     pub rabbitmq: RabbitMQConfig,
     pub kafka: KafkaConfig,
+    #[serde(default)]
+    pub processor: ProcessorConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+fn default_instance_id() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
 }
 
 impl AppConfig {
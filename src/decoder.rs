@@ -0,0 +1,183 @@
+use crate::config::{PacketKind, ProcessorConfig, RsmConfig};
+use crate::error::Result;
+use crate::gt06::Gt06Decoder;
+use crate::nmea::NmeaDecoder;
+use crate::rsm::RsmDecoder;
+use crate::teltonika::TeltonikaDecoder;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// A message decoded into the canonical source fields used by the output schema
+/// (see `schema.rs` for the field names).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedRecord {
+    /// Selects the output schema and topic.
+    pub kind: PacketKind,
+    pub fields: Map<String, Value>,
+}
+
+impl DecodedRecord {
+    pub fn new(kind: PacketKind, fields: Map<String, Value>) -> Self {
+        Self { kind, fields }
+    }
+
+    /// Value of a source field as a string, if present and non-empty.
+    pub fn text(&self, field: &str) -> Option<String> {
+        match self.fields.get(field)? {
+            Value::String(value) if value.is_empty() => None,
+            Value::String(value) => Some(value.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// A telematics wire format the bridge can decode.
+pub trait ProtocolDecoder: Send + Sync {
+    /// Protocol name, matched case-insensitively against the protocol AMQP header.
+    fn name(&self) -> &'static str;
+
+    /// Whether the payload looks like this protocol.
+    fn detect(&self, payload: &[u8]) -> bool;
+
+    /// Split a payload into frames that are decoded independently, so one bad frame
+    /// does not cause the rest of the payload to be rejected. By default the whole
+    /// payload is a single frame.
+    fn frames<'a>(&self, payload: &'a [u8]) -> Vec<&'a [u8]> {
+        vec![payload]
+    }
+
+    /// Decode a frame into one or more records. `device_id` is the identifier
+    /// supplied by the gateway in the device ID AMQP header, for protocols whose
+    /// packets do not carry one.
+    fn decode(&self, payload: &[u8], device_id: Option<&str>) -> Result<Vec<DecodedRecord>>;
+
+    /// Read a single source field (such as `imei`) cheaply from the raw payload,
+    /// without fully decoding it. Used to keep each device's messages in order
+    /// and to key payloads that fail to decode.
+    fn peek_field<'a>(&self, _payload: &'a [u8], _field: &str) -> Option<Cow<'a, str>> {
+        None
+    }
+}
+
+/// Decoders available to the processor, tried in registration order.
+pub struct DecoderRegistry {
+    decoders: Vec<Box<dyn ProtocolDecoder>>,
+}
+
+impl DecoderRegistry {
+    pub fn new(config: &ProcessorConfig) -> Self {
+        let mut registry = Self { decoders: Vec::new() };
+        let window = GpsTimeWindow::new(&config.rsm);
+        registry.register(Box::new(RsmDecoder::new(config.rsm.clone(), window)));
+        registry.register(Box::new(TeltonikaDecoder::new(window)));
+        registry.register(Box::new(Gt06Decoder::new(window)));
+        registry.register(Box::new(NmeaDecoder::new(window)));
+        registry
+    }
+
+    pub fn register(&mut self, decoder: Box<dyn ProtocolDecoder>) {
+        info!("Registered {} protocol decoder", decoder.name());
+        self.decoders.push(decoder);
+    }
+
+    /// Pick a decoder by explicit protocol name when one is given, otherwise by
+    /// sniffing the payload.
+    pub fn select(&self, payload: &[u8], protocol: Option<&str>) -> Option<&dyn ProtocolDecoder> {
+        let decoder = match protocol {
+            Some(protocol) => self
+                .decoders
+                .iter()
+                .find(|decoder| decoder.name().eq_ignore_ascii_case(protocol)),
+            None => self.decoders.iter().find(|decoder| decoder.detect(payload)),
+        };
+        decoder.map(|decoder| decoder.as_ref())
+    }
+}
+
+/// Unwrap a `json!` object literal into its map.
+pub fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(fields) => fields,
+        _ => unreachable!("json! object literal is always an object"),
+    }
+}
+
+/// Accepted distance between a reported GPS time and the bridge clock. Times
+/// outside it are forwarded but flagged as suspect.
+#[derive(Debug, Clone, Copy)]
+pub struct GpsTimeWindow {
+    max_future_skew: chrono::Duration,
+    max_past_age: chrono::Duration,
+}
+
+impl GpsTimeWindow {
+    pub fn new(config: &RsmConfig) -> Self {
+        Self {
+            max_future_skew: chrono::Duration::seconds(config.max_future_skew_secs as i64),
+            max_past_age: chrono::Duration::seconds(config.max_past_age_secs as i64),
+        }
+    }
+
+    pub fn is_suspect(&self, gps_time: DateTime<Utc>, device_id: &str) -> bool {
+        let now = Utc::now();
+        let suspect = gps_time > now + self.max_future_skew || gps_time < now - self.max_past_age;
+        if suspect {
+            warn!("GPS time {} from device {} is outside the accepted window", gps_time, device_id);
+        }
+        suspect
+    }
+}
+
+/// Big-endian cursor over a binary payload. Reads past the end return `None`.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Bytes read since `start`.
+    pub fn since(&self, start: usize) -> &'a [u8] {
+        &self.data[start..self.position]
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position.checked_add(len)?)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.array().map(u8::from_be_bytes)
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+}
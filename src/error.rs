@@ -1,6 +1,7 @@
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum AppError {
     #[error("Configuration error: {0}")]
     ConfigError(#[from] config::ConfigError),
@@ -11,6 +12,12 @@ pub enum AppError {
     #[error("Kafka producer error: {0}")]
     KafkaProducerError(String),
     
+    #[error("Kafka rejected the record for topic {topic}: {reason}")]
+    KafkaRecordRejected { topic: String, reason: String },
+    
+    #[error("Kafka write to topic {0} abandoned at the shutdown deadline")]
+    KafkaSendAborted(String),
+    
     #[error("JSON deserialization error: {0}")]
     JsonDeserializationError(#[from] serde_json::Error),
     
@@ -18,7 +25,206 @@ pub enum AppError {
     IoError(#[from] std::io::Error),
     
     #[error("RSM protocol parsing error: {0}")]
-    RsmParsingError(String),
+    RsmParsingError(#[from] RsmParseError),
+    
+    #[error("Teltonika protocol parsing error: {0}")]
+    TeltonikaParsingError(#[from] TeltonikaParseError),
+    
+    #[error("GT06 protocol parsing error: {0}")]
+    Gt06ParsingError(#[from] Gt06ParseError),
+    
+    #[error("NMEA sentence parsing error: {0}")]
+    NmeaParsingError(#[from] NmeaParseError),
+    
+    #[error("Output mapping error: {0}")]
+    MappingError(#[from] MappingError),
+    
+    #[error("Unsupported protocol: {0}")]
+    UnsupportedProtocol(String),
+}
+
+impl AppError {
+    /// Short, stable label for the kind of failure.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::ConfigError(_) => "config_error",
+            AppError::RabbitMQConnectionError(_) => "rabbitmq_error",
+            AppError::KafkaProducerError(_) => "kafka_error",
+            AppError::KafkaRecordRejected { .. } => "kafka_rejected",
+            AppError::KafkaSendAborted(_) => "kafka_aborted",
+            AppError::JsonDeserializationError(_) => "json_error",
+            AppError::IoError(_) => "io_error",
+            AppError::RsmParsingError(e) => e.reason(),
+            AppError::TeltonikaParsingError(e) => e.reason(),
+            AppError::Gt06ParsingError(e) => e.reason(),
+            AppError::NmeaParsingError(e) => e.reason(),
+            AppError::MappingError(_) => "mapping_error",
+            AppError::UnsupportedProtocol(_) => "unsupported_protocol",
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RsmParseError {
+    #[error("Invalid RSM message format: insufficient fields ({0})")]
+    InsufficientFields(usize),
+    
+    #[error("Message does not start with $RSM")]
+    InvalidHeader,
+    
+    #[error("Missing value for field '{field}'")]
+    MissingField { field: &'static str },
+    
+    #[error("Invalid value '{value}' for field '{field}'")]
+    InvalidField { field: &'static str, value: String },
+    
+    #[error("Value {value} for field '{field}' is out of range")]
+    OutOfRange { field: &'static str, value: f64 },
+    
+    #[error("Unrecognised timestamp '{0}'")]
+    InvalidTimestamp(String),
+    
+    #[error("Checksum mismatch: packet carries '{expected}', computed '{actual}'")]
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TeltonikaParseError {
+    #[error("Packet truncated while reading {0}")]
+    Truncated(&'static str),
+    
+    #[error("Packet does not start with the AVL preamble")]
+    InvalidPreamble,
+    
+    #[error("Data field length {declared} does not match the {actual} bytes received")]
+    LengthMismatch { declared: usize, actual: usize },
+    
+    #[error("Unsupported codec 0x{0:02X}")]
+    UnsupportedCodec(u8),
+    
+    #[error("Record count {header} in the header does not match {trailer} in the trailer")]
+    RecordCountMismatch { header: u8, trailer: u8 },
+    
+    #[error("CRC-16 mismatch: packet carries 0x{expected:04X}, computed 0x{actual:04X}")]
+    ChecksumMismatch { expected: u32, actual: u16 },
+    
+    #[error("Invalid timestamp {0}")]
+    InvalidTimestamp(u64),
+    
+    #[error("Value {value} for field '{field}' is out of range")]
+    OutOfRange { field: &'static str, value: f64 },
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Gt06ParseError {
+    #[error("Packet truncated while reading {0}")]
+    Truncated(&'static str),
+    
+    #[error("Packet does not start with 0x7878 or 0x7979")]
+    InvalidStartBits,
+    
+    #[error("Packet does not end with 0x0D0A")]
+    InvalidStopBits,
+    
+    #[error("CRC-ITU mismatch: packet carries 0x{expected:04X}, computed 0x{actual:04X}")]
+    ChecksumMismatch { expected: u16, actual: u16 },
+    
+    #[error("Unsupported protocol number 0x{0:02X}")]
+    UnsupportedPacket(u8),
+    
+    #[error("Invalid date/time {0}")]
+    InvalidTimestamp(String),
+    
+    #[error("Value {value} for field '{field}' is out of range")]
+    OutOfRange { field: &'static str, value: f64 },
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum NmeaParseError {
+    #[error("Sentence does not start with $")]
+    InvalidHeader,
+    
+    #[error("Sentence has no *hh checksum")]
+    MissingChecksum,
+    
+    #[error("Checksum mismatch: sentence carries {expected}, computed {actual:02X}")]
+    ChecksumMismatch { expected: String, actual: u8 },
+    
+    #[error("Unsupported sentence '{0}'")]
+    UnsupportedSentence(String),
+    
+    #[error("Invalid NMEA sentence: insufficient fields ({0})")]
+    InsufficientFields(usize),
+    
+    /// A field rejected by the field parsers shared with the RSM decoder.
+    #[error(transparent)]
+    Field(#[from] RsmParseError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MappingError {
+    #[error("Cannot convert {value} from source field '{source_field}' to {target}")]
+    Conversion { source_field: String, value: String, target: &'static str },
+}
+
+impl RsmParseError {
+    /// Short, stable label for the kind of failure.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RsmParseError::InsufficientFields(_) => "insufficient_fields",
+            RsmParseError::InvalidHeader => "invalid_header",
+            RsmParseError::MissingField { .. } => "missing_field",
+            RsmParseError::InvalidField { .. } => "invalid_field",
+            RsmParseError::OutOfRange { .. } => "out_of_range",
+            RsmParseError::InvalidTimestamp(_) => "invalid_timestamp",
+            RsmParseError::ChecksumMismatch { .. } => "checksum_mismatch",
+        }
+    }
+}
+
+impl TeltonikaParseError {
+    /// Short, stable label for the kind of failure.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TeltonikaParseError::Truncated(_) => "truncated",
+            TeltonikaParseError::InvalidPreamble => "invalid_header",
+            TeltonikaParseError::LengthMismatch { .. } => "length_mismatch",
+            TeltonikaParseError::UnsupportedCodec(_) => "unsupported_codec",
+            TeltonikaParseError::RecordCountMismatch { .. } => "record_count_mismatch",
+            TeltonikaParseError::ChecksumMismatch { .. } => "checksum_mismatch",
+            TeltonikaParseError::InvalidTimestamp(_) => "invalid_timestamp",
+            TeltonikaParseError::OutOfRange { .. } => "out_of_range",
+        }
+    }
+}
+
+impl Gt06ParseError {
+    /// Short, stable label for the kind of failure.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Gt06ParseError::Truncated(_) => "truncated",
+            Gt06ParseError::InvalidStartBits => "invalid_header",
+            Gt06ParseError::InvalidStopBits => "invalid_footer",
+            Gt06ParseError::ChecksumMismatch { .. } => "checksum_mismatch",
+            Gt06ParseError::UnsupportedPacket(_) => "unsupported_packet",
+            Gt06ParseError::InvalidTimestamp(_) => "invalid_timestamp",
+            Gt06ParseError::OutOfRange { .. } => "out_of_range",
+        }
+    }
+}
+
+impl NmeaParseError {
+    /// Short, stable label for the kind of failure.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            NmeaParseError::InvalidHeader => "invalid_header",
+            NmeaParseError::MissingChecksum => "missing_checksum",
+            NmeaParseError::ChecksumMismatch { .. } => "checksum_mismatch",
+            NmeaParseError::UnsupportedSentence(_) => "unsupported_sentence",
+            NmeaParseError::InsufficientFields(_) => "insufficient_fields",
+            NmeaParseError::Field(e) => e.reason(),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
\ No newline at end of file
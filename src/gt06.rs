@@ -0,0 +1,455 @@
+use crate::config::PacketKind;
+use crate::decoder::{self, ByteReader, DecodedRecord, GpsTimeWindow, ProtocolDecoder};
+use crate::error::{self, Gt06ParseError};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use serde_json::json;
+use std::borrow::Cow;
+
+const SHORT_START: [u8; 2] = [0x78, 0x78];
+const LONG_START: [u8; 2] = [0x79, 0x79];
+const STOP: [u8; 2] = [0x0D, 0x0A];
+
+const LOGIN: u8 = 0x01;
+const LOCATION: u8 = 0x12;
+const HEARTBEAT: u8 = 0x13;
+const ALARM: u8 = 0x16;
+const LOCATION_EXTENDED: u8 = 0x22;
+const ALARM_EXTENDED: u8 = 0x26;
+
+/// A single GT06 / Concox packet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gt06Packet {
+    Login { imei: String, serial: u16 },
+    Location { location: Location, serial: u16 },
+    Heartbeat { status: TerminalStatus, serial: u16 },
+    Alarm { location: Location, status: TerminalStatus, alarm: u8, serial: u16 },
+}
+
+/// GPS and cell position shared by location and alarm packets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub gps_time: DateTime<Utc>,
+    pub satellites: u8,
+    /// Decimal degrees, negative for S.
+    pub latitude: f64,
+    /// Decimal degrees, negative for W.
+    pub longitude: f64,
+    /// Speed in km/h.
+    pub speed: u8,
+    /// Heading in degrees from north.
+    pub course: u16,
+    pub positioned: bool,
+    pub mcc: Option<u16>,
+    pub mnc: Option<u8>,
+    pub lac: Option<u16>,
+    pub cell_id: Option<u32>,
+    /// ACC (ignition) state, only reported by extended location packets.
+    pub acc: Option<bool>,
+    /// Odometer in metres, only reported by some extended location packets.
+    pub mileage: Option<u32>,
+}
+
+/// Terminal information reported by heartbeat and alarm packets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerminalStatus {
+    pub armed: bool,
+    pub acc: bool,
+    pub charging: bool,
+    pub gps_tracking: bool,
+    pub fuel_cut: bool,
+    /// Battery voltage level from 0 (no power) to 6 (full).
+    pub voltage_level: u8,
+    /// GSM signal strength from 0 (none) to 4 (strong).
+    pub gsm_signal_strength: u8,
+}
+
+impl Gt06Packet {
+    /// Parse every packet in a payload. Devices that upload buffered positions in
+    /// one transmission send several complete packets back to back.
+    pub fn parse_all(payload: &[u8]) -> Result<Vec<Self>, Gt06ParseError> {
+        let mut reader = ByteReader::new(payload);
+        let mut packets = Vec::new();
+
+        while reader.remaining() > 0 {
+            packets.push(Self::parse(&mut reader)?);
+        }
+
+        Ok(packets)
+    }
+
+    fn parse(reader: &mut ByteReader) -> Result<Self, Gt06ParseError> {
+        let start = read(reader.bytes(2), "start bits")?;
+        let checked_from = reader.position();
+
+        // The length counts the protocol number, content, serial number and CRC
+        let length = match start {
+            s if s == SHORT_START => read(reader.u8(), "packet length")? as usize,
+            s if s == LONG_START => read(reader.u16(), "packet length")? as usize,
+            _ => return Err(Gt06ParseError::InvalidStartBits),
+        };
+        let content_length = length.checked_sub(5).ok_or(Gt06ParseError::Truncated("packet length"))?;
+
+        let protocol = read(reader.u8(), "protocol number")?;
+        let content = read(reader.bytes(content_length), "information content")?;
+        let serial = read(reader.u16(), "serial number")?;
+
+        let crc = crc_itu(reader.since(checked_from));
+        let expected = read(reader.u16(), "CRC")?;
+        if expected != crc {
+            return Err(Gt06ParseError::ChecksumMismatch { expected, actual: crc });
+        }
+
+        if read(reader.bytes(2), "stop bits")? != STOP {
+            return Err(Gt06ParseError::InvalidStopBits);
+        }
+
+        let mut content = ByteReader::new(content);
+        match protocol {
+            LOGIN => Ok(Gt06Packet::Login { imei: imei(read(content.bytes(8), "terminal ID")?), serial }),
+            LOCATION | LOCATION_EXTENDED => {
+                let mut location = Location::parse(&mut content)?;
+                if protocol == LOCATION_EXTENDED {
+                    location.acc = content.u8().map(|acc| acc != 0);
+                    content.bytes(2);
+                    location.mileage = content.u32();
+                }
+                Ok(Gt06Packet::Location { location, serial })
+            },
+            HEARTBEAT => {
+                let status = TerminalStatus::parse(&mut content)?;
+                Ok(Gt06Packet::Heartbeat { status, serial })
+            },
+            ALARM | ALARM_EXTENDED => {
+                let location = Location::parse_with_lbs_length(&mut content)?;
+                let status = TerminalStatus::parse(&mut content)?;
+                let alarm = read(content.u8(), "alarm")?;
+                Ok(Gt06Packet::Alarm { location, status, alarm, serial })
+            },
+            other => Err(Gt06ParseError::UnsupportedPacket(other)),
+        }
+    }
+}
+
+impl Location {
+    fn parse(reader: &mut ByteReader) -> Result<Self, Gt06ParseError> {
+        let mut location = Self::parse_gps(reader)?;
+        location.parse_lbs(reader);
+        Ok(location)
+    }
+
+    /// Alarm packets prefix the cell information with its length.
+    fn parse_with_lbs_length(reader: &mut ByteReader) -> Result<Self, Gt06ParseError> {
+        let mut location = Self::parse_gps(reader)?;
+        let length = read(reader.u8(), "LBS length")? as usize;
+        let lbs = read(reader.bytes(length.saturating_sub(1)), "LBS information")?;
+        location.parse_lbs(&mut ByteReader::new(lbs));
+        Ok(location)
+    }
+
+    fn parse_gps(reader: &mut ByteReader) -> Result<Self, Gt06ParseError> {
+        let date_time = read(reader.bytes(6), "date/time")?;
+        let gps_time = NaiveDate::from_ymd_opt(2000 + date_time[0] as i32, date_time[1] as u32, date_time[2] as u32)
+            .and_then(|date| date.and_hms_opt(date_time[3] as u32, date_time[4] as u32, date_time[5] as u32))
+            .map(|date_time| date_time.and_utc())
+            .ok_or_else(|| Gt06ParseError::InvalidTimestamp(format!("{:?}", date_time)))?;
+
+        // High nibble is the GPS information length, low nibble the satellite count
+        let satellites = read(reader.u8(), "GPS information")? & 0x0F;
+        let latitude = read(reader.u32(), "latitude")? as f64 / 1_800_000.0;
+        let longitude = read(reader.u32(), "longitude")? as f64 / 1_800_000.0;
+        let speed = read(reader.u8(), "speed")?;
+        let course_status = read(reader.u16(), "course/status")?;
+
+        let latitude = if course_status & 0x0400 != 0 { latitude } else { -latitude };
+        let longitude = if course_status & 0x0800 != 0 { -longitude } else { longitude };
+
+        if latitude.abs() > 90.0 {
+            return Err(Gt06ParseError::OutOfRange { field: "latitude", value: latitude });
+        }
+        if longitude.abs() > 180.0 {
+            return Err(Gt06ParseError::OutOfRange { field: "longitude", value: longitude });
+        }
+
+        Ok(Self {
+            gps_time,
+            satellites,
+            latitude,
+            longitude,
+            speed,
+            course: course_status & 0x03FF,
+            positioned: course_status & 0x1000 != 0,
+            mcc: None,
+            mnc: None,
+            lac: None,
+            cell_id: None,
+            acc: None,
+            mileage: None,
+        })
+    }
+
+    /// Cell information is optional; firmwares without a GSM fix leave it out.
+    fn parse_lbs(&mut self, reader: &mut ByteReader) {
+        if reader.remaining() < 8 {
+            return;
+        }
+        self.mcc = reader.u16();
+        self.mnc = reader.u8();
+        self.lac = reader.u16();
+        self.cell_id = reader.bytes(3).map(|id| u32::from_be_bytes([0, id[0], id[1], id[2]]));
+    }
+}
+
+impl TerminalStatus {
+    fn parse(reader: &mut ByteReader) -> Result<Self, Gt06ParseError> {
+        let info = read(reader.u8(), "terminal information")?;
+        let voltage_level = read(reader.u8(), "voltage level")?;
+        let gsm_signal_strength = read(reader.u8(), "GSM signal strength")?;
+
+        Ok(Self {
+            armed: info & 0x01 != 0,
+            acc: info & 0x02 != 0,
+            charging: info & 0x04 != 0,
+            gps_tracking: info & 0x40 != 0,
+            fuel_cut: info & 0x80 != 0,
+            voltage_level,
+            gsm_signal_strength,
+        })
+    }
+}
+
+/// Decoder for GT06 / Concox binary packets.
+pub struct Gt06Decoder {
+    window: GpsTimeWindow,
+}
+
+impl Gt06Decoder {
+    pub fn new(window: GpsTimeWindow) -> Self {
+        Self { window }
+    }
+
+    /// Normalise a packet into canonical source fields: login packets to a login
+    /// record, heartbeats to a health record, locations to a tracking record and
+    /// alarms to an emergency record.
+    fn source_fields(&self, packet: &Gt06Packet, imei: Option<&str>) -> DecodedRecord {
+        match packet {
+            Gt06Packet::Login { imei, serial } => DecodedRecord::new(PacketKind::Login, decoder::object(json!({
+                "header": "gt06",
+                "vendor_id": "gt06",
+                "packet_type": "login",
+                "imei": imei,
+                "serial_number": serial,
+            }))),
+            Gt06Packet::Heartbeat { status, serial } => {
+                let mut fields = decoder::object(json!({
+                    "header": "gt06",
+                    "vendor_id": "gt06",
+                    "packet_type": "heartbeat",
+                    "imei": imei,
+                    "serial_number": serial,
+                }));
+                fields.extend(status_fields(status));
+                DecodedRecord::new(PacketKind::Health, fields)
+            },
+            Gt06Packet::Location { location, serial } => {
+                let mut fields = self.location_fields(location, imei);
+                fields.extend(decoder::object(json!({
+                    "packet_type": "location",
+                    "serial_number": serial,
+                    "ignition": location.acc,
+                    "mileage": location.mileage,
+                })));
+                DecodedRecord::new(PacketKind::Tracking, fields)
+            },
+            Gt06Packet::Alarm { location, status, alarm, serial } => {
+                let mut fields = self.location_fields(location, imei);
+                fields.extend(status_fields(status));
+                fields.extend(decoder::object(json!({
+                    "packet_type": "alarm",
+                    "serial_number": serial,
+                    "alarm_type": alarm_type(*alarm),
+                    "emergency_status": *alarm == 0x01,
+                })));
+                DecodedRecord::new(PacketKind::Emergency, fields)
+            },
+        }
+    }
+
+    fn location_fields(&self, location: &Location, imei: Option<&str>) -> serde_json::Map<String, serde_json::Value> {
+        let suspect = self.window.is_suspect(location.gps_time, imei.unwrap_or("unknown"));
+
+        decoder::object(json!({
+            "header": "gt06",
+            "vendor_id": "gt06",
+            "imei": imei,
+            "gps_fix": location.positioned,
+            "gps_time": location.gps_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "gps_time_suspect": suspect,
+            "latitude": location.latitude,
+            "longitude": location.longitude,
+            "speed": location.speed,
+            "heading": location.course,
+            "satellites": location.satellites,
+            "mcc": location.mcc,
+            "mnc": location.mnc,
+            "lac": location.lac,
+            "cell_id": location.cell_id,
+        }))
+    }
+}
+
+impl ProtocolDecoder for Gt06Decoder {
+    fn name(&self) -> &'static str {
+        "gt06"
+    }
+
+    fn detect(&self, payload: &[u8]) -> bool {
+        payload.starts_with(&SHORT_START) || payload.starts_with(&LONG_START)
+    }
+
+    /// Split back-to-back packets using their length fields. Anything that cannot be
+    /// framed, such as unknown start bits or a truncated packet, becomes the last frame.
+    fn frames<'a>(&self, payload: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut frames = Vec::new();
+        let mut rest = payload;
+
+        while !rest.is_empty() {
+            // Start bits, length field and stop bits around the counted bytes
+            let framed_length = if rest.starts_with(&SHORT_START) {
+                rest.get(2).map(|length| *length as usize + 5)
+            } else if rest.starts_with(&LONG_START) {
+                rest.get(2..4).map(|length| u16::from_be_bytes([length[0], length[1]]) as usize + 6)
+            } else {
+                None
+            };
+            let Some(length) = framed_length.filter(|length| *length <= rest.len()) else {
+                frames.push(rest);
+                break;
+            };
+            let (frame, remainder) = rest.split_at(length);
+            frames.push(frame);
+            rest = remainder;
+        }
+
+        frames
+    }
+
+    fn decode(&self, payload: &[u8], device_id: Option<&str>) -> error::Result<Vec<DecodedRecord>> {
+        let packets = Gt06Packet::parse_all(payload)?;
+
+        // Only login packets carry the IMEI; later packets in the same upload inherit it
+        let mut imei = device_id.map(str::to_string);
+        Ok(packets
+            .iter()
+            .map(|packet| {
+                if let Gt06Packet::Login { imei: login, .. } = packet {
+                    imei = Some(login.clone());
+                }
+                self.source_fields(packet, imei.as_deref())
+            })
+            .collect())
+    }
+
+    fn peek_field<'a>(&self, payload: &'a [u8], field: &str) -> Option<Cow<'a, str>> {
+        // Only login packets carry the IMEI: 0x7878, length, protocol number, terminal ID
+        match field {
+            "imei" if payload.starts_with(&SHORT_START) && payload.get(3) == Some(&LOGIN) => {
+                payload.get(4..12).map(|terminal_id| Cow::Owned(imei(terminal_id)))
+            },
+            _ => None,
+        }
+    }
+}
+
+fn status_fields(status: &TerminalStatus) -> serde_json::Map<String, serde_json::Value> {
+    decoder::object(json!({
+        "ignition": status.acc,
+        "armed": status.armed,
+        "charging": status.charging,
+        "gps_tracking": status.gps_tracking,
+        "fuel_cut": status.fuel_cut,
+        "voltage_level": status.voltage_level,
+        "gsm_signal_strength": status.gsm_signal_strength,
+    }))
+}
+
+fn alarm_type(alarm: u8) -> Cow<'static, str> {
+    let name = match alarm {
+        0x00 => "none",
+        0x01 => "sos",
+        0x02 => "power_cut",
+        0x03 => "vibration",
+        0x04 => "geofence_enter",
+        0x05 => "geofence_exit",
+        0x06 => "overspeed",
+        0x09 => "movement",
+        other => return Cow::Owned(format!("unknown_{:02x}", other)),
+    };
+    Cow::Borrowed(name)
+}
+
+/// Terminal ID as 8 BCD bytes: a padding digit followed by the 15-digit IMEI.
+fn imei(terminal_id: &[u8]) -> String {
+    let digits: String = terminal_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+    digits[digits.len().saturating_sub(15)..].to_string()
+}
+
+/// CRC-ITU (CRC-16/X-25) over everything from the packet length to the serial number.
+fn crc_itu(data: &[u8]) -> u16 {
+    let crc = data.iter().fold(0xFFFFu16, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u16, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 }
+        })
+    });
+    !crc
+}
+
+fn read<T>(value: Option<T>, field: &'static str) -> Result<T, Gt06ParseError> {
+    value.ok_or(Gt06ParseError::Truncated(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Login packet from the GT06 protocol documentation.
+    const LOGIN_SAMPLE: [u8; 18] = [
+        0x78, 0x78, 0x0D, 0x01, 0x01, 0x23, 0x45, 0x67, 0x89, 0x01, 0x23, 0x45, 0x00, 0x01, 0x8C, 0xDD, 0x0D, 0x0A,
+    ];
+
+    fn decoder() -> Gt06Decoder {
+        Gt06Decoder::new(GpsTimeWindow::new(&Default::default()))
+    }
+
+    #[test]
+    fn crc_itu_matches_published_sample() {
+        assert_eq!(crc_itu(&LOGIN_SAMPLE[2..14]), 0x8CDD);
+    }
+
+    #[test]
+    fn parses_published_login() {
+        assert_eq!(
+            Gt06Packet::parse_all(&LOGIN_SAMPLE),
+            Ok(vec![Gt06Packet::Login { imei: "123456789012345".to_string(), serial: 1 }])
+        );
+    }
+
+    #[test]
+    fn splits_back_to_back_packets_so_a_bad_crc_stays_isolated() {
+        let mut corrupted = LOGIN_SAMPLE;
+        corrupted[15] ^= 0x01;
+        let payload = [LOGIN_SAMPLE, corrupted, LOGIN_SAMPLE].concat();
+
+        let decoder = decoder();
+        let frames = decoder.frames(&payload);
+        assert_eq!(frames, vec![&LOGIN_SAMPLE[..], &corrupted[..], &LOGIN_SAMPLE[..]]);
+        assert!(decoder.decode(frames[0], None).is_ok());
+        assert!(decoder.decode(frames[1], None).is_err());
+        assert!(decoder.decode(frames[2], None).is_ok());
+    }
+
+    #[test]
+    fn keeps_unframeable_remainder_as_last_frame() {
+        let payload = [&LOGIN_SAMPLE[..], &LOGIN_SAMPLE[..10]].concat();
+        assert_eq!(decoder().frames(&payload), vec![&LOGIN_SAMPLE[..], &LOGIN_SAMPLE[..10]]);
+    }
+}
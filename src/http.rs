@@ -0,0 +1,87 @@
+use crate::config::HttpConfig;
+use crate::error::Result;
+use crate::kafka::KafkaProducer;
+use crate::metrics;
+use crate::rabbitmq::ConnectionState;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use lapin::Channel;
+use log::{info, warn};
+use serde_json::json;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+const KAFKA_METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connection handles consulted by the readiness probe.
+#[derive(Clone)]
+pub struct Readiness {
+    pub rabbitmq: watch::Receiver<ConnectionState>,
+    pub rabbitmq_channel: watch::Receiver<Option<Channel>>,
+    pub kafka: KafkaProducer,
+}
+
+/// Serve the operational HTTP endpoints until shutdown is requested.
+pub async fn serve(config: HttpConfig, readiness: Readiness, shutdown: CancellationToken) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(liveness_handler))
+        .route("/readyz", get(readiness_handler))
+        .with_state(readiness);
+
+    let listener = TcpListener::bind(&config.address).await?;
+    info!("HTTP server listening on {}", config.address);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics::render())
+}
+
+async fn liveness_handler() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn readiness_handler(State(readiness): State<Readiness>) -> impl IntoResponse {
+    // The state alone lags behind a connection that has just failed, so check the channel itself
+    let rabbitmq_ready = *readiness.rabbitmq.borrow() == ConnectionState::Connected
+        && readiness
+            .rabbitmq_channel
+            .borrow()
+            .as_ref()
+            .is_some_and(|channel| channel.status().connected());
+
+    let kafka_ready = match readiness.kafka.check_connectivity(KAFKA_METADATA_TIMEOUT).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Readiness check could not reach Kafka: {}", e);
+            false
+        }
+    };
+
+    let status = if rabbitmq_ready && kafka_ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = json!({
+        "status": if status == StatusCode::OK { "ready" } else { "not_ready" },
+        "rabbitmq": if rabbitmq_ready { "connected" } else { "disconnected" },
+        "kafka": if kafka_ready { "connected" } else { "unreachable" },
+    });
+
+    (status, Json(body))
+}
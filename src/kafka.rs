@@ -1,11 +1,17 @@
+use crate::backoff::Backoff;
 use crate::error::{AppError, Result};
-use crate::config::KafkaConfig;
-use log::{info, error};
+use crate::config::{KafkaConfig, PacketKind};
+use crate::metrics;
+use log::{debug, info, warn, error};
 use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use rdkafka::util::Timeout;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
+#[derive(Clone)]
 pub struct KafkaProducer {
     config: KafkaConfig,
     producer: FutureProducer,
@@ -15,10 +21,29 @@ impl KafkaProducer {
     pub fn new(config: KafkaConfig) -> Result<Self> {
         info!("Initializing Kafka producer with brokers: {}", config.brokers);
         
-        // Create a Kafka producer
-        let producer: FutureProducer = ClientConfig::new()
+        let security = config.security.client_properties().map_err(|e| {
+            error!("Invalid Kafka security configuration: {}", e);
+            AppError::ConfigError(e)
+        })?;
+        info!("Kafka security protocol: {:?}", config.security.protocol);
+        
+        let mut client_config = ClientConfig::new();
+        client_config
             .set("bootstrap.servers", &config.brokers)
-            .set("message.timeout.ms", "5000")
+            .set("message.timeout.ms", config.send_timeout_ms.to_string());
+        for (key, value) in security {
+            client_config.set(key, value);
+        }
+        
+        // Values are not logged, as they may include credentials
+        for (key, value) in &config.producer {
+            let key = key.replace('_', ".");
+            debug!("Setting Kafka producer property {}", key);
+            client_config.set(key, value);
+        }
+        
+        // Create a Kafka producer
+        let producer: FutureProducer = client_config
             .create()
             .map_err(|e| {
                 error!("Failed to create Kafka producer: {}", e);
@@ -30,30 +55,146 @@ impl KafkaProducer {
         Ok(Self { config, producer })
     }
     
-    pub async fn send_valid_message(&self, payload: &str, key: Option<&str>) -> Result<()> {
-        self.send_message(&self.config.valid_topic, payload, key).await
+    pub async fn send_valid_message(&self, kind: PacketKind, payload: &str, key: Option<&str>, abort: &CancellationToken) -> Result<()> {
+        self.send_message(self.config.topic(kind), payload.as_bytes(), key, None, abort).await
     }
     
-    pub async fn send_invalid_message(&self, payload: &str, key: Option<&str>) -> Result<()> {
-        self.send_message(&self.config.invalid_topic, payload, key).await
+    /// Forward a payload that could not be decoded, byte for byte, with `headers`
+    /// describing why it was rejected.
+    pub async fn send_invalid_message(
+        &self,
+        payload: &[u8],
+        key: Option<&str>,
+        headers: &[(&str, String)],
+        abort: &CancellationToken,
+    ) -> Result<()> {
+        let headers = headers.iter().fold(OwnedHeaders::new_with_capacity(headers.len()), |headers, (key, value)| {
+            headers.insert(Header { key, value: Some(value.as_str()) })
+        });
+        self.send_message(&self.config.invalid_topic, payload, key, Some(&headers), abort).await
     }
     
-    async fn send_message(&self, topic: &str, payload: &str, key: Option<&str>) -> Result<()> {
+    /// Check that at least one broker answers a metadata request within `timeout`.
+    pub async fn check_connectivity(&self, timeout: Duration) -> Result<()> {
+        let producer = self.producer.clone();
+        let metadata = tokio::task::spawn_blocking(move || {
+            producer.client().fetch_metadata(None, Timeout::After(timeout))
+        })
+        .await
+        .map_err(|e| AppError::KafkaProducerError(e.to_string()))?
+        .map_err(|e| AppError::KafkaProducerError(e.to_string()))?;
+        
+        if metadata.brokers().is_empty() {
+            return Err(AppError::KafkaProducerError("No Kafka brokers available".to_string()));
+        }
+        
+        Ok(())
+    }
+    
+    /// Wait for all outstanding produce requests to complete.
+    pub async fn flush(&self, timeout: Duration) -> Result<()> {
+        info!("Flushing Kafka producer");
+        
+        let producer = self.producer.clone();
+        tokio::task::spawn_blocking(move || producer.flush(Timeout::After(timeout)))
+            .await
+            .map_err(|e| AppError::KafkaProducerError(e.to_string()))?
+            .map_err(|e| {
+                error!("Failed to flush Kafka producer: {}", e);
+                AppError::KafkaProducerError(e.to_string())
+            })
+    }
+    
+    /// Produce a record, retrying transient failures with backoff. Records Kafka
+    /// will never accept fail straight away with `KafkaRecordRejected`, and once
+    /// `abort` is cancelled the write is given up with `KafkaSendAborted`.
+    async fn send_message(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        key: Option<&str>,
+        headers: Option<&OwnedHeaders>,
+        abort: &CancellationToken,
+    ) -> Result<()> {
         info!("Sending message to Kafka topic: {}", topic);
         
-        let record = FutureRecord::to(topic)
-            .payload(payload)
-            .key(key.unwrap_or(""));
-        
-        match self.producer.send(record, Timeout::After(Duration::from_secs(5))).await {
-            Ok((partition, offset)) => {
-                info!("Message sent to Kafka topic: {}, partition: {}, offset: {}", topic, partition, offset);
-                Ok(())
-            },
-            Err((e, _)) => {
-                error!("Failed to send message to Kafka: {}", e);
-                Err(AppError::KafkaProducerError(e.to_string()))
+        let retry = &self.config.retry;
+        let mut backoff = Backoff::new(retry.initial_backoff_ms, retry.max_backoff_ms, retry.multiplier, retry.jitter);
+        let timeout = Timeout::After(Duration::from_millis(self.config.send_timeout_ms));
+        
+        loop {
+            let mut record = FutureRecord::<str, [u8]>::to(topic).payload(payload);
+            if let Some(key) = key {
+                record = record.key(key);
+            }
+            if let Some(headers) = headers {
+                record = record.headers(headers.clone());
+            }
+            
+            let started = Instant::now();
+            let result = tokio::select! {
+                biased;
+                _ = abort.cancelled() => return Err(aborted(topic)),
+                result = self.producer.send(record, timeout) => result,
+            };
+            metrics::KAFKA_PRODUCE_DURATION
+                .with_label_values(&[topic])
+                .observe(started.elapsed().as_secs_f64());
+            
+            let e = match result {
+                Ok((partition, offset)) => {
+                    info!("Message sent to Kafka topic: {}, partition: {}, offset: {}", topic, partition, offset);
+                    metrics::KAFKA_MESSAGES_SENT.with_label_values(&[topic, "success"]).inc();
+                    return Ok(());
+                },
+                Err((e, _)) => e,
+            };
+            
+            if is_permanent(&e) {
+                error!("Kafka rejected message for topic {}: {}", topic, e);
+                metrics::KAFKA_MESSAGES_SENT.with_label_values(&[topic, "rejected"]).inc();
+                return Err(AppError::KafkaRecordRejected { topic: topic.to_string(), reason: e.to_string() });
+            }
+            
+            if backoff.attempt() + 1 >= retry.max_attempts {
+                error!("Failed to send message to Kafka after {} attempts: {}", backoff.attempt() + 1, e);
+                metrics::KAFKA_MESSAGES_SENT.with_label_values(&[topic, "failure"]).inc();
+                return Err(AppError::KafkaProducerError(e.to_string()));
+            }
+            
+            let delay = backoff.next_delay();
+            warn!("Failed to send message to Kafka topic {}: {}, retrying in {:?} (attempt {})", topic, e, delay, backoff.attempt());
+            metrics::KAFKA_MESSAGES_SENT.with_label_values(&[topic, "retry"]).inc();
+            tokio::select! {
+                biased;
+                _ = abort.cancelled() => return Err(aborted(topic)),
+                _ = tokio::time::sleep(delay) => {},
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn aborted(topic: &str) -> AppError {
+    warn!("Abandoning Kafka write to topic {} at the shutdown deadline", topic);
+    metrics::KAFKA_MESSAGES_SENT.with_label_values(&[topic, "aborted"]).inc();
+    AppError::KafkaSendAborted(topic.to_string())
+}
+
+/// Whether an error is specific to the record, so retrying it can never succeed.
+/// Everything else (full queue, unavailable broker, timeouts) is treated as transient.
+fn is_permanent(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::MessageSizeTooLarge
+                | RDKafkaErrorCode::InvalidMessageSize
+                | RDKafkaErrorCode::InvalidMessage
+                | RDKafkaErrorCode::InvalidRecord
+                | RDKafkaErrorCode::UnknownTopic
+                | RDKafkaErrorCode::UnknownTopicOrPartition
+                | RDKafkaErrorCode::InvalidTopic
+                | RDKafkaErrorCode::TopicAuthorizationFailed
+                | RDKafkaErrorCode::UnsupportedForMessageFormat
+        )
+    )
+}
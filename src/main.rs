@@ -1,8 +1,18 @@
+mod ais140;
+mod backoff;
 mod config;
+mod decoder;
 mod error;
+mod gt06;
+mod http;
 mod kafka;
+mod metrics;
+mod nmea;
 mod processor;
 mod rabbitmq;
+mod rsm;
+mod schema;
+mod teltonika;
 
 use config::AppConfig;
 use error::{AppError, Result};
@@ -10,8 +20,10 @@ use kafka::KafkaProducer;
 use processor::MessageProcessor;
 use rabbitmq::RabbitMQConsumer;
 use log::{info, error};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::task;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -24,23 +36,44 @@ async fn main() -> Result<()> {
         AppError::ConfigError(e)
     })?;
     
-    info!("Configuration loaded successfully");
+    info!("Configuration loaded successfully (instance: {})", config.instance_id);
+
+    metrics::init();
 
     let (sender, receiver) = mpsc::channel(100);
 
     let kafka_producer = KafkaProducer::new(config.kafka)?;
 
-    let processor = MessageProcessor::new(kafka_producer);
+    let source_queue = config.rabbitmq.queue.clone();
+    let rabbitmq_consumer = RabbitMQConsumer::new(config.rabbitmq, &config.instance_id, config.processor.workers)?;
+
+    let readiness = http::Readiness {
+        rabbitmq: rabbitmq_consumer.state(),
+        rabbitmq_channel: rabbitmq_consumer.channel(),
+        kafka: kafka_producer.clone(),
+    };
+
+    let processor = Arc::new(MessageProcessor::new(kafka_producer, config.processor, source_queue, config.instance_id));
+
+    let shutdown = CancellationToken::new();
 
-    let rabbitmq_consumer = RabbitMQConsumer::new(config.rabbitmq);
+    task::spawn(wait_for_shutdown_signal(shutdown.clone()));
 
+    let http_shutdown = shutdown.clone();
+    task::spawn(async move {
+        if let Err(e) = http::serve(config.http, readiness, http_shutdown).await {
+            error!("HTTP server error: {}", e);
+        }
+    });
+
+    let processor_shutdown = shutdown.clone();
     let processor_handle = task::spawn(async move {
-        if let Err(e) = processor.start(receiver).await {
+        if let Err(e) = processor.start(receiver, processor_shutdown).await {
             error!("Message processor error: {}", e);
         }
     });
 
-    if let Err(e) = rabbitmq_consumer.start(sender).await {
+    if let Err(e) = rabbitmq_consumer.start(sender, shutdown).await {
         error!("RabbitMQ consumer error: {}", e);
         return Err(e);
     }
@@ -49,7 +82,41 @@ async fn main() -> Result<()> {
         error!("Failed to join processor task: {}", e);
     }
     
+    rabbitmq_consumer.close().await;
+    
     info!("RabbitMQ to Kafka bridge stopped");
     
     Ok(())
 }
+
+/// Cancel `shutdown` once SIGINT or SIGTERM is received.
+async fn wait_for_shutdown_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(e) => {
+                error!("Failed to install SIGTERM handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                error!("Failed to listen for SIGINT: {}", e);
+                return;
+            }
+            info!("Received SIGINT, shutting down");
+        },
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+    
+    shutdown.cancel();
+}
@@ -0,0 +1,88 @@
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+    core::Collector,
+};
+use std::sync::LazyLock;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static MESSAGES_CONSUMED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "bridge_rabbitmq_messages_consumed_total",
+        "Messages received from RabbitMQ",
+    ))
+});
+
+pub static CHANNEL_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "bridge_processor_channel_depth",
+        "Deliveries waiting in the channel between the RabbitMQ consumer and the processor",
+    ))
+});
+
+pub static ACKNOWLEDGEMENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bridge_rabbitmq_acknowledgements_total", "RabbitMQ acks and nacks sent"),
+        &["result"],
+    ))
+});
+
+pub static MESSAGES_PARSED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bridge_messages_parsed_total", "Messages successfully parsed and transformed"),
+        &["protocol"],
+    ))
+});
+
+pub static MESSAGES_REJECTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bridge_messages_rejected_total", "Messages routed to the invalid topic"),
+        &["reason"],
+    ))
+});
+
+pub static KAFKA_MESSAGES_SENT: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bridge_kafka_messages_sent_total", "Kafka produce attempts"),
+        &["topic", "status"],
+    ))
+});
+
+pub static KAFKA_PRODUCE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "bridge_kafka_produce_duration_seconds",
+            "Time from producing a record until Kafka acknowledges it",
+        ),
+        &["topic"],
+    ))
+});
+
+fn register<T: Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric registered twice");
+    metric
+}
+
+/// Register every metric up front so they are exported before their first update.
+pub fn init() {
+    LazyLock::force(&MESSAGES_CONSUMED);
+    LazyLock::force(&CHANNEL_DEPTH);
+    LazyLock::force(&ACKNOWLEDGEMENTS);
+    LazyLock::force(&MESSAGES_PARSED);
+    LazyLock::force(&MESSAGES_REJECTED);
+    LazyLock::force(&KAFKA_MESSAGES_SENT);
+    LazyLock::force(&KAFKA_PRODUCE_DURATION);
+}
+
+/// Render every registered metric in the Prometheus text exposition format.
+pub fn render() -> String {
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .unwrap_or_else(|e| {
+            log::error!("Failed to encode metrics: {}", e);
+            String::new()
+        })
+}
@@ -0,0 +1,218 @@
+use crate::config::{CoordinateFormat, PacketKind};
+use crate::decoder::{self, DecodedRecord, GpsTimeWindow, ProtocolDecoder};
+use crate::error::{self, NmeaParseError};
+use crate::rsm::{self, Fields};
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use serde_json::json;
+
+/// Knots to km/h.
+const KNOTS_TO_KMH: f64 = 1.852;
+
+/// Sentence types the decoder understands, with any talker ID.
+const SENTENCES: &[&str] = &["RMC", "GGA"];
+
+/// Talker IDs accepted in front of the sentence type: GPS only, or any GNSS.
+const TALKERS: &[&str] = &["GP", "GN"];
+
+/// A validated NMEA 0183 sentence.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NmeaSentence {
+    /// Recommended minimum data: time, date, position, speed and course.
+    Rmc {
+        talker: String,
+        gps_time: DateTime<Utc>,
+        active: bool,
+        latitude: f64,
+        longitude: f64,
+        /// Speed over ground in knots.
+        speed_knots: f64,
+        course: Option<f64>,
+    },
+    /// Fix data: time of day, position, fix quality, satellites, HDOP and altitude.
+    Gga {
+        talker: String,
+        gps_time: DateTime<Utc>,
+        latitude: f64,
+        longitude: f64,
+        /// 0 = invalid, 1 = GPS, 2 = DGPS, 4/5 = RTK, 6 = dead reckoning.
+        fix_quality: u8,
+        satellites: Option<u8>,
+        hdop: Option<f64>,
+        altitude: Option<f64>,
+    },
+}
+
+impl NmeaSentence {
+    /// Parse a sentence, verifying its `*hh` checksum. GGA sentences carry only the
+    /// time of day, so they are dated against `now`.
+    pub fn parse(sentence: &str, now: DateTime<Utc>) -> Result<Self, NmeaParseError> {
+        let sentence = sentence.trim();
+        let body = sentence.strip_prefix('$').ok_or(NmeaParseError::InvalidHeader)?;
+        let (body, checksum) = body.split_once('*').ok_or(NmeaParseError::MissingChecksum)?;
+
+        let actual = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+        if u8::from_str_radix(checksum.trim(), 16).ok() != Some(actual) {
+            return Err(NmeaParseError::ChecksumMismatch { expected: checksum.trim().to_string(), actual });
+        }
+
+        let parts: Vec<&str> = body.split(',').collect();
+        let (talker, kind) = sentence_type(parts[0]).ok_or_else(|| NmeaParseError::UnsupportedSentence(parts[0].to_string()))?;
+        if parts.len() < 10 {
+            return Err(NmeaParseError::InsufficientFields(parts.len()));
+        }
+        let fields = Fields::new(parts);
+
+        match kind {
+            "RMC" => {
+                let time = fields.required(1, "time", parse_time)?;
+                let date = fields.required(9, "date", |v| NaiveDate::parse_from_str(v, "%d%m%y").ok())?;
+                let (latitude, longitude) = position(&fields, 3)?;
+
+                Ok(NmeaSentence::Rmc {
+                    talker: talker.to_string(),
+                    gps_time: date.and_time(time).and_utc(),
+                    active: fields.required(2, "status", |v| match v {
+                        "A" => Some(true),
+                        "V" => Some(false),
+                        _ => None,
+                    })?,
+                    latitude,
+                    longitude,
+                    speed_knots: fields.required(7, "speed", rsm::parse_f64)?,
+                    course: fields.optional(8, "course", rsm::parse_f64)?,
+                })
+            },
+            _ => {
+                let time = fields.required(1, "time", parse_time)?;
+                let (latitude, longitude) = position(&fields, 2)?;
+
+                // Date the fix today, or yesterday if that would put it in the future
+                let today = now.date_naive().and_time(time).and_utc();
+                let gps_time = if today > now {
+                    today.checked_sub_days(Days::new(1)).unwrap_or(today)
+                } else {
+                    today
+                };
+
+                Ok(NmeaSentence::Gga {
+                    talker: talker.to_string(),
+                    gps_time,
+                    latitude,
+                    longitude,
+                    fix_quality: fields.required(6, "fix_quality", |v| v.parse().ok())?,
+                    satellites: fields.optional(7, "satellites", |v| v.parse().ok())?,
+                    hdop: fields.optional(8, "hdop", rsm::parse_f64)?,
+                    altitude: fields.optional(9, "altitude", rsm::parse_f64)?,
+                })
+            },
+        }
+    }
+}
+
+/// Decoder for NMEA 0183 `RMC` and `GGA` sentences, one sentence per line.
+pub struct NmeaDecoder {
+    window: GpsTimeWindow,
+}
+
+impl NmeaDecoder {
+    pub fn new(window: GpsTimeWindow) -> Self {
+        Self { window }
+    }
+
+    /// Normalise a sentence into the canonical tracking source fields.
+    fn source_fields(&self, sentence: &NmeaSentence, imei: Option<&str>) -> DecodedRecord {
+        let device = imei.unwrap_or("unknown");
+
+        let fields = match sentence {
+            NmeaSentence::Rmc { talker, gps_time, active, latitude, longitude, speed_knots, course } => json!({
+                "header": format!("${}RMC", talker),
+                "vendor_id": "nmea",
+                "packet_type": "RMC",
+                "imei": imei,
+                "gps_fix": active,
+                "gps_time": gps_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "gps_time_suspect": self.window.is_suspect(*gps_time, device),
+                "latitude": latitude,
+                "longitude": longitude,
+                "speed": speed_knots * KNOTS_TO_KMH,
+                "heading": course,
+            }),
+            NmeaSentence::Gga { talker, gps_time, latitude, longitude, fix_quality, satellites, hdop, altitude } => json!({
+                "header": format!("${}GGA", talker),
+                "vendor_id": "nmea",
+                "packet_type": "GGA",
+                "imei": imei,
+                "gps_fix": *fix_quality > 0,
+                "fix_quality": fix_quality,
+                "gps_time": gps_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "gps_time_suspect": self.window.is_suspect(*gps_time, device),
+                "latitude": latitude,
+                "longitude": longitude,
+                "satellites": satellites,
+                "hdop": hdop,
+                "altitude": altitude,
+            }),
+        };
+
+        DecodedRecord::new(PacketKind::Tracking, decoder::object(fields))
+    }
+}
+
+impl ProtocolDecoder for NmeaDecoder {
+    fn name(&self) -> &'static str {
+        "nmea"
+    }
+
+    fn detect(&self, payload: &[u8]) -> bool {
+        payload
+            .strip_prefix(b"$")
+            .and_then(|rest| rest.get(..5))
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .is_some_and(|name| sentence_type(name).is_some())
+    }
+
+    /// One sentence per line, so a corrupted sentence does not take the rest with it.
+    fn frames<'a>(&self, payload: &'a [u8]) -> Vec<&'a [u8]> {
+        payload
+            .split(|byte| *byte == b'\n')
+            .map(<[u8]>::trim_ascii)
+            .filter(|frame| !frame.is_empty())
+            .collect()
+    }
+
+    fn decode(&self, payload: &[u8], device_id: Option<&str>) -> error::Result<Vec<DecodedRecord>> {
+        let payload = String::from_utf8_lossy(payload);
+        let now = Utc::now();
+
+        payload
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let sentence = NmeaSentence::parse(line, now)?;
+                Ok(self.source_fields(&sentence, device_id))
+            })
+            .collect()
+    }
+}
+
+/// Split a sentence name such as `GPRMC` into its talker ID and sentence type.
+fn sentence_type(name: &str) -> Option<(&str, &str)> {
+    let (talker, kind) = name.split_at_checked(2)?;
+    (TALKERS.contains(&talker) && SENTENCES.contains(&kind)).then_some((talker, kind))
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H%M%S%.f")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H%M%S"))
+        .ok()
+}
+
+/// Signed decimal degrees from the `ddmm.mmmm,N,dddmm.mmmm,E` fields starting at `index`.
+fn position(fields: &Fields, index: usize) -> Result<(f64, f64), NmeaParseError> {
+    let latitude = fields.required(index, "latitude", rsm::parse_f64)?;
+    let latitude_dir = fields.required(index + 1, "latitude_dir", rsm::parse_latitude_dir)?;
+    let longitude = fields.required(index + 2, "longitude", rsm::parse_f64)?;
+    let longitude_dir = fields.required(index + 3, "longitude_dir", rsm::parse_longitude_dir)?;
+
+    Ok(rsm::position(CoordinateFormat::DegreesMinutes, (latitude, latitude_dir), (longitude, longitude_dir))?)
+}
@@ -1,175 +1,434 @@
+use crate::config::{AckMode, KeyStrategy, PacketKind, ProcessorConfig};
+use crate::decoder::{DecodedRecord, DecoderRegistry, ProtocolDecoder};
 use crate::error::{AppError, Result};
 use crate::kafka::KafkaProducer;
+use crate::metrics;
+use crate::rabbitmq::ReceivedDelivery;
+use crate::schema::OutputSchemas;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::FutureExt;
 use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicNackOptions};
+use lapin::types::AMQPValue;
 use log::{info, warn, error, debug};
 use serde_json::Value;
+use std::borrow::Cow;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use std::collections::HashMap;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+const WORKER_QUEUE_CAPACITY: usize = 16;
+/// Time workers get to requeue their queues once the shutdown deadline has passed.
+const ABORT_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 pub struct MessageProcessor {
     kafka_producer: KafkaProducer,
+    decoders: DecoderRegistry,
+    schemas: OutputSchemas,
+    config: ProcessorConfig,
+    /// Queue the deliveries are consumed from, recorded on rejected records.
+    source_queue: String,
+    /// Identifies this bridge instance on rejected records.
+    instance_id: String,
 }
 
 impl MessageProcessor {
-    pub fn new(kafka_producer: KafkaProducer) -> Self {
-        Self { kafka_producer }
+    pub fn new(kafka_producer: KafkaProducer, mut config: ProcessorConfig, source_queue: String, instance_id: String) -> Self {
+        info!("Message processor acknowledgement mode: {:?}", config.ack_mode);
+        let decoders = DecoderRegistry::new(&config);
+        let schemas = OutputSchemas::new(std::mem::take(&mut config.output));
+        Self { kafka_producer, decoders, schemas, config, source_queue, instance_id }
     }
 
-    pub async fn start(&self, mut message_receiver: mpsc::Receiver<Delivery>) -> Result<()> {
-        info!("Message processor started");
+    /// Dispatch deliveries to a pool of workers until shutdown is requested.
+    /// Deliveries from the same device always go to the same worker, so each
+    /// device's messages are processed in the order they were received.
+    pub async fn start(self: Arc<Self>, mut message_receiver: mpsc::Receiver<ReceivedDelivery>, shutdown: CancellationToken) -> Result<()> {
+        let pool = WorkerPool::spawn(&self, self.config.workers.max(1));
+        info!("Message processor started with {} workers", pool.senders.len());
         
-        while let Some(delivery) = message_receiver.recv().await {
-            self.process_message(delivery).await?;
+        loop {
+            let delivery = tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                delivery = message_receiver.recv() => delivery,
+            };
+            
+            metrics::CHANNEL_DEPTH.set(message_receiver.len() as i64);
+            
+            match delivery {
+                Some(received) => pool.dispatch(received, None).await,
+                None => {
+                    pool.join().await;
+                    return Ok(());
+                }
+            }
         }
         
-        Ok(())
+        self.drain(message_receiver, pool).await
     }
     
-    async fn process_message(&self, delivery: Delivery) -> Result<()> {
-        let payload = String::from_utf8_lossy(&delivery.data);
-        info!("Processing message: {}", payload);
-        
-        let result = if payload.starts_with("$RSM") {
-            debug!("Detected RSM protocol message");
-            match self.parse_rsm_message(&payload) {
-                Ok(parsed_data) => {
-                    info!("Successfully parsed RSM protocol message");
-                    let transformed = self.transform_rsm_to_vehicle_tracking_format(parsed_data);
-                    self.kafka_producer.send_valid_message(
-                        &serde_json::to_string(&transformed).unwrap_or_else(|_| payload.to_string()),
-                        None
-                    ).await
-                },
-                Err(e) => {
-                    warn!("Failed to parse RSM protocol message: {}", e);
-                    self.kafka_producer.send_invalid_message(&payload, None).await
-                }
+    /// Process whatever is still queued once shutdown has been requested, then flush Kafka.
+    /// Deliveries left over when the deadline passes are requeued on RabbitMQ.
+    async fn drain(&self, mut message_receiver: mpsc::Receiver<ReceivedDelivery>, pool: WorkerPool) -> Result<()> {
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let deadline = Instant::now() + timeout;
+        info!("Draining in-flight messages (timeout: {:?})", timeout);
+        
+        let mut closed = false;
+        while !closed && Instant::now() < deadline {
+            match tokio::time::timeout_at(deadline, message_receiver.recv()).await {
+                Ok(Some(received)) => pool.dispatch(received, Some(deadline)).await,
+                Ok(None) => closed = true,
+                Err(_) => {},
             }
-        } else {
-            warn!("Received non-RSM message, sending to invalid topic");
-            self.kafka_producer.send_invalid_message(&payload, None).await
-        };
+        }
         
-        if let Err(e) = delivery.ack(lapin::options::BasicAckOptions::default()).await {
-            error!("Failed to acknowledge message: {}", e);
+        if !closed {
+            pool.abort.cancel();
+            message_receiver.close();
+            while let Some(received) = message_receiver.recv().await {
+                self.nack(&received.delivery, true).await;
+            }
         }
         
-        result
+        let abort = pool.abort.clone();
+        let workers = pool.join();
+        tokio::pin!(workers);
+        
+        if tokio::time::timeout_at(deadline, &mut workers).await.is_err() || abort.is_cancelled() {
+            warn!("Shutdown deadline reached, requeueing remaining messages");
+            abort.cancel();
+            if tokio::time::timeout(ABORT_GRACE_PERIOD, workers).await.is_err() {
+                error!("Workers did not stop within {:?} of the shutdown deadline, abandoning them", ABORT_GRACE_PERIOD);
+            }
+        }
+        
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        self.kafka_producer.flush(remaining).await?;
+        
+        info!("Message processor drained");
+        Ok(())
     }
     
-
+    /// Process deliveries until the worker's channel closes. A failed delivery has
+    /// already been acknowledged or rejected by `process_message`, so its error is
+    /// only logged and must never stop the worker. A delivery that panics is
+    /// dead-lettered, as redelivering it would only panic again.
+    async fn run_worker(self: Arc<Self>, mut receiver: mpsc::Receiver<ReceivedDelivery>, abort: CancellationToken) {
+        while let Some(received) = receiver.recv().await {
+            if abort.is_cancelled() {
+                self.nack(&received.delivery, true).await;
+                continue;
+            }
+            
+            match AssertUnwindSafe(self.process_message(&received, &abort)).catch_unwind().await {
+                Ok(Ok(())) => {},
+                Ok(Err(e)) => error!("Failed to process message: {}", e),
+                Err(_) => {
+                    error!("Worker panicked while processing a message, dead-lettering it");
+                    self.nack(&received.delivery, false).await;
+                },
+            }
+        }
+    }
     
-    fn parse_rsm_message(&self, message: &str) -> Result<HashMap<String, String>> {
-        let parts: Vec<&str> = message.split(',').collect();
-        
-        if parts.len() < 20 {
-            return Err(AppError::RsmParsingError(format!("Invalid RSM message format: insufficient fields ({})", parts.len())));
+    async fn process_message(&self, received: &ReceivedDelivery, abort: &CancellationToken) -> Result<()> {
+        let ReceivedDelivery { delivery, received_at } = received;
+        let received_at = *received_at;
+        match std::str::from_utf8(&delivery.data) {
+            Ok(payload) => info!("Processing message: {}", payload),
+            Err(_) => info!("Processing binary message ({} bytes)", delivery.data.len()),
         }
         
-        if !parts[0].starts_with("$RSM") {
-            return Err(AppError::RsmParsingError("Message does not start with $RSM".to_string()));
+        if self.config.ack_mode == AckMode::AtMostOnce {
+            self.ack(delivery).await;
         }
         
-        let mut parsed_data = HashMap::new();
-        
-        // Define field mappings with indices
-        let field_mappings = [
-            ("header", 0), ("vendor_id", 1), ("firmware_version", 2),
-            ("packet_type", 3), ("packet_status", 5), ("imei", 6),
-            ("vehicle_reg_no", 7), ("gps_fix", 8), ("date_time", 9),
-            ("latitude", 10), ("latitude_dir", 11), ("longitude", 12),
-            ("longitude_dir", 13), ("speed", 14), ("heading", 15),
-            ("satellites", 16), ("altitude", 17), ("pdop", 18),
-            ("hdop", 19), ("network_operator", 20), ("ignition", 21),
-            ("main_power_status", 22), ("main_input_voltage", 23),
-            ("internal_battery_voltage", 24), ("emergency_status", 25),
-            ("tamper_alert", 26), ("gsm_signal_strength", 27),
-            ("mcc", 28), ("mnc", 29)
-        ];
+        let protocol = header(delivery, &self.config.protocol_header);
+        let result = match self.decoders.select(&delivery.data, protocol.as_deref()) {
+            Some(decoder) => {
+                debug!("Detected {} protocol message", decoder.name());
+                self.process_frames(decoder, delivery, received_at, abort).await
+            },
+            None => {
+                let e = AppError::UnsupportedProtocol(protocol.unwrap_or_else(|| "unrecognised payload".to_string()));
+                warn!("{}, sending to invalid topic", e);
+                let key = self.invalid_message_key(None, &delivery.data, delivery);
+                self.send_invalid(&delivery.data, key, &e, delivery, received_at, abort).await
+            }
+        };
         
-        // Insert fields if they exist in the parts array
-        for (field, index) in field_mappings.iter() {
-            if parts.len() > *index {
-                parsed_data.insert(field.to_string(), parts[*index].to_string());
+        if self.config.ack_mode == AckMode::AtLeastOnce {
+            match &result {
+                Ok(()) => self.ack(delivery).await,
+                Err(AppError::KafkaRecordRejected { .. }) => {
+                    // Requeueing would only fail again, so hand the delivery to the dead-letter exchange
+                    warn!("Kafka rejected the message, dead-lettering it");
+                    self.nack(delivery, false).await
+                },
+                Err(AppError::KafkaSendAborted(_)) => self.nack(delivery, true).await,
+                Err(_) => {
+                    warn!("Kafka write failed, rejecting message (requeue: {})", self.config.requeue_on_failure);
+                    self.nack(delivery, self.config.requeue_on_failure).await
+                },
             }
         }
         
-        Ok(parsed_data)
+        result
     }
     
-    fn transform_rsm_to_vehicle_tracking_format(&self, rsm_data: HashMap<String, String>) -> Value {
-        let mut result = serde_json::Map::new();
-        
-        // Helper closure for inserting string values
-        let insert_str = |map: &mut serde_json::Map<String, Value>, key: &str, value: &str| {
-            map.insert(key.to_string(), Value::String(value.to_string()));
-        };
+    /// Decode every frame of a delivery and publish the result. Frames that fail to
+    /// decode go to the invalid topic on their own, without affecting the others.
+    async fn process_frames(
+        &self,
+        decoder: &dyn ProtocolDecoder,
+        delivery: &Delivery,
+        received_at: DateTime<Utc>,
+        abort: &CancellationToken,
+    ) -> Result<()> {
+        let mut device_id = header(delivery, &self.config.device_id_header);
+        let frames = decoder.frames(&delivery.data);
+        if frames.len() > 1 {
+            debug!("Split {} protocol message into {} frames", decoder.name(), frames.len());
+        }
         
-        // Helper closure for parsing and inserting numeric values
-        let parse_and_insert_f64 = |map: &mut serde_json::Map<String, Value>, key: &str, value_str: &str| {
-            if let Ok(value) = value_str.parse::<f64>() {
-                if let Some(num) = serde_json::Number::from_f64(value) {
-                    map.insert(key.to_string(), Value::Number(num));
+        for frame in frames {
+            match self.decode(decoder, frame, device_id.as_deref()) {
+                Ok(records) => {
+                    info!("Successfully decoded {} protocol frame", decoder.name());
+                    // Some protocols only send the IMEI in the login packet; the frames after it inherit it
+                    let login = records.iter().find(|(record, _)| record.kind == PacketKind::Login);
+                    if let Some(imei) = login.and_then(|(record, _)| record.text("imei")) {
+                        device_id = Some(imei);
+                    }
+                    metrics::MESSAGES_PARSED.with_label_values(&[decoder.name()]).inc();
+                    match self.send_valid_records(records, abort).await {
+                        Err(e @ AppError::KafkaRecordRejected { .. }) => {
+                            warn!("{}, sending {} protocol frame to invalid topic", e, decoder.name());
+                            let key = self.invalid_message_key(Some(decoder), frame, delivery);
+                            self.send_invalid(frame, key, &e, delivery, received_at, abort).await?;
+                        },
+                        result => result?,
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to decode {} protocol frame: {}", decoder.name(), e);
+                    let key = self.invalid_message_key(Some(decoder), frame, delivery);
+                    self.send_invalid(frame, key, &e, delivery, received_at, abort).await?;
                 }
             }
-        };
+        }
         
+        Ok(())
+    }
+    
+    /// Decode a frame and map every record through the output schema.
+    fn decode(
+        &self,
+        decoder: &dyn ProtocolDecoder,
+        payload: &[u8],
+        device_id: Option<&str>,
+    ) -> Result<Vec<(DecodedRecord, Value)>> {
+        decoder
+            .decode(payload, device_id)?
+            .into_iter()
+            .map(|record| {
+                let transformed = self.schemas.get(record.kind).apply(&record.fields)?;
+                Ok((record, transformed))
+            })
+            .collect()
+    }
+    
+    async fn send_valid_records(&self, records: Vec<(DecodedRecord, Value)>, abort: &CancellationToken) -> Result<()> {
+        for (record, transformed) in records {
+            let key = self.valid_message_key(&record, &transformed);
+            self.kafka_producer
+                .send_valid_message(record.kind, &serde_json::to_string(&transformed)?, key.as_deref(), abort)
+                .await?;
+        }
+        Ok(())
+    }
+    
+    async fn send_invalid(
+        &self,
+        payload: &[u8],
+        key: Option<String>,
+        error: &AppError,
+        delivery: &Delivery,
+        received_at: DateTime<Utc>,
+        abort: &CancellationToken,
+    ) -> Result<()> {
+        metrics::MESSAGES_REJECTED.with_label_values(&[error.kind()]).inc();
+        let headers = self.rejection_headers(error, delivery, received_at);
+        self.kafka_producer.send_invalid_message(payload, key.as_deref(), &headers, abort).await
+    }
+    
+    /// Kafka headers telling whoever triages the invalid topic why a payload was
+    /// rejected and where it came from. AMQP properties the publisher did not set are left out.
+    fn rejection_headers(&self, error: &AppError, delivery: &Delivery, received_at: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let properties = &delivery.properties;
+        let mut headers = vec![
+            ("x-error", error.to_string()),
+            ("x-error-kind", error.kind().to_string()),
+            ("x-source-queue", self.source_queue.clone()),
+            ("x-amqp-exchange", delivery.exchange.to_string()),
+            ("x-amqp-routing-key", delivery.routing_key.to_string()),
+        ];
         
-        // deviceId - Unique identifier for the tracking device
-        if let Some(imei) = rsm_data.get("imei") {
-            insert_str(&mut result, "deviceId", imei);
+        if let Some(message_id) = properties.message_id() {
+            headers.push(("x-amqp-message-id", message_id.to_string()));
         }
         
-        // gpsTime - Date and time in UTC
-        if let Some(date_time) = rsm_data.get("date_time") {
-            // Format the date_time to ISO 8601 format with UTC timezone if needed
-            // For now, just use the value as is
-            insert_str(&mut result, "gpsTime", date_time);
-        } 
+        let timestamp = properties
+            .timestamp()
+            .and_then(|seconds| DateTime::from_timestamp(i64::try_from(seconds).ok()?, 0));
+        if let Some(timestamp) = timestamp {
+            headers.push(("x-amqp-timestamp", timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)));
+        }
         
-        // deviceSpeed - Speed of the device in km/h or mph
-        if let Some(speed_str) = rsm_data.get("speed") {
-            parse_and_insert_f64(&mut result, "deviceSpeed", speed_str);
-        } 
+        headers.push(("x-bridge-instance", self.instance_id.clone()));
+        headers.push(("x-received-at", received_at.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        headers
+    }
+    
+    async fn ack(&self, delivery: &Delivery) {
+        match delivery.ack(BasicAckOptions::default()).await {
+            Ok(()) => metrics::ACKNOWLEDGEMENTS.with_label_values(&["ack"]).inc(),
+            Err(e) => error!("Failed to acknowledge message: {}", e),
+        }
+    }
+    
+    async fn nack(&self, delivery: &Delivery, requeue: bool) {
+        match delivery.nack(BasicNackOptions { requeue, ..Default::default() }).await {
+            Ok(()) => metrics::ACKNOWLEDGEMENTS.with_label_values(&["nack"]).inc(),
+            Err(e) => error!("Failed to reject message: {}", e),
+        }
+    }
+    
+    /// Kafka key for a successfully decoded record, per the configured key strategy.
+    fn valid_message_key(&self, record: &DecodedRecord, transformed: &Value) -> Option<String> {
+        match &self.config.key {
+            KeyStrategy::Imei => record.text("imei"),
+            KeyStrategy::VehicleRegNo => record.text("vehicle_reg_no"),
+            KeyStrategy::None => None,
+            KeyStrategy::JsonPointer { pointer } => json_pointer_key(transformed, pointer),
+        }
+    }
+    
+    /// Best-effort Kafka key for a payload (or one frame of it) that could not be decoded.
+    fn invalid_message_key(&self, decoder: Option<&dyn ProtocolDecoder>, payload: &[u8], delivery: &Delivery) -> Option<String> {
+        let peek = |field| decoder?.peek_field(payload, field).map(Cow::into_owned);
+        match &self.config.key {
+            KeyStrategy::Imei => peek("imei").or_else(|| header(delivery, &self.config.device_id_header)),
+            KeyStrategy::VehicleRegNo => peek("vehicle_reg_no"),
+            KeyStrategy::None => None,
+            KeyStrategy::JsonPointer { pointer } => serde_json::from_slice::<Value>(payload)
+                .ok()
+                .and_then(|value| json_pointer_key(&value, pointer)),
+        }
+    }
+    
+    /// Key used to pin a delivery to a worker: the device identifier if one can be found
+    /// cheaply, otherwise the routing key, so deliveries that cannot be attributed to a
+    /// device still keep the order in which they were published under that routing key.
+    fn ordering_key<'a>(&self, delivery: &'a Delivery) -> Cow<'a, str> {
+        let protocol = header(delivery, &self.config.protocol_header);
+        self.decoders
+            .select(&delivery.data, protocol.as_deref())
+            .and_then(|decoder| decoder.peek_field(&delivery.data, "imei"))
+            .or_else(|| header(delivery, &self.config.device_id_header).map(Cow::Owned))
+            .unwrap_or(Cow::Borrowed(delivery.routing_key.as_str()))
+    }
+}
+
+/// Fixed set of worker tasks, each fed by its own channel.
+struct WorkerPool {
+    processor: Arc<MessageProcessor>,
+    senders: Vec<mpsc::Sender<ReceivedDelivery>>,
+    handles: Vec<JoinHandle<()>>,
+    /// Cancelled when the shutdown deadline passes; workers then requeue instead of processing.
+    abort: CancellationToken,
+}
+
+impl WorkerPool {
+    fn spawn(processor: &Arc<MessageProcessor>, workers: usize) -> Self {
+        let abort = CancellationToken::new();
+        let (senders, handles) = (0..workers)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel(WORKER_QUEUE_CAPACITY);
+                let handle = tokio::spawn(processor.clone().run_worker(receiver, abort.clone()));
+                (sender, handle)
+            })
+            .unzip();
         
-        // orientation - Direction in degrees
-        if let Some(heading_str) = rsm_data.get("heading") {
-            parse_and_insert_f64(&mut result, "orientation", heading_str);
-        } 
+        Self { processor: processor.clone(), senders, handles, abort }
+    }
+    
+    /// Hand a delivery to its worker. With a `deadline`, a delivery the worker has no
+    /// room for by then is requeued instead.
+    async fn dispatch(&self, received: ReceivedDelivery, deadline: Option<Instant>) {
+        let mut hasher = DefaultHasher::new();
+        self.processor.ordering_key(&received.delivery).hash(&mut hasher);
+        let index = (hasher.finish() % self.senders.len() as u64) as usize;
         
-        // latitude - Geographic coordinate
-        if let Some(lat_str) = rsm_data.get("latitude") {
-            if let Ok(mut lat_value) = lat_str.parse::<f64>() {
-                if let Some(dir) = rsm_data.get("latitude_dir") {
-                    if dir == "S" {
-                        lat_value = -lat_value;
-                    }
-                }
-                if let Some(num) = serde_json::Number::from_f64(lat_value) {
-                    result.insert("latitude".to_string(), Value::Number(num));
-                }
-            }
-        } 
-        
-        // longitude - Geographic coordinate
-        if let Some(lng_str) = rsm_data.get("longitude") {
-            if let Ok(mut lng_value) = lng_str.parse::<f64>() {
-                if let Some(dir) = rsm_data.get("longitude_dir") {
-                    if dir == "W" {
-                        lng_value = -lng_value;
-                    }
-                }
-                if let Some(num) = serde_json::Number::from_f64(lng_value) {
-                    result.insert("longitude".to_string(), Value::Number(num));
-                }
-            }
-        } 
+        let sender = &self.senders[index];
+        let result = match deadline {
+            Some(deadline) => sender.send_timeout(received, deadline.saturating_duration_since(Instant::now())).await,
+            None => sender.send(received).await.map_err(|mpsc::error::SendError(received)| {
+                mpsc::error::SendTimeoutError::Closed(received)
+            }),
+        };
         
-        // provider - Unique client ID for VTS
-        if let Some(network_operator) = rsm_data.get("network_operator") {
-            insert_str(&mut result, "provider", network_operator);
-        } 
+        let received = match result {
+            Ok(()) => return,
+            Err(mpsc::error::SendTimeoutError::Timeout(received)) => {
+                warn!("Shutdown deadline reached while worker {} was busy, requeueing message", index);
+                received
+            },
+            Err(mpsc::error::SendTimeoutError::Closed(received)) => {
+                error!("Worker {} is no longer running, requeueing message", index);
+                received
+            },
+        };
         
-        Value::Object(result)
+        match received.delivery.nack(BasicNackOptions { requeue: true, ..Default::default() }).await {
+            Ok(()) => metrics::ACKNOWLEDGEMENTS.with_label_values(&["nack"]).inc(),
+            Err(e) => error!("Failed to reject message: {}", e),
+        }
+    }
+    
+    /// Close the worker channels and wait for every worker to finish its queue.
+    async fn join(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            if let Err(e) = handle.await {
+                error!("Failed to join worker task: {}", e);
+            }
+        }
+    }
+}
+
+/// String value of an AMQP header, if present and non-empty.
+fn header(delivery: &Delivery, name: &str) -> Option<String> {
+    let value = delivery.properties.headers().as_ref()?.inner().get(name)?;
+    match value {
+        AMQPValue::LongString(value) => Some(value.to_string()),
+        AMQPValue::ShortString(value) => Some(value.to_string()),
+        _ => None,
+    }
+    .filter(|value| !value.is_empty())
+}
+
+fn json_pointer_key(value: &Value, pointer: &str) -> Option<String> {
+    match value.pointer(pointer)? {
+        Value::Null => None,
+        Value::String(key) => Some(key.clone()),
+        other => Some(other.to_string()),
     }
-}
\ No newline at end of file
+}
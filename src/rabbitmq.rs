@@ -1,42 +1,230 @@
+use crate::backoff::Backoff;
 use crate::error::{AppError, Result};
-use crate::config::RabbitMQConfig;
-use lapin::{Connection, ConnectionProperties, options::*, types::FieldTable};
+use crate::config::{invalid, AmqpAuthMechanism, RabbitMQConfig, RabbitMQTlsConfig};
+use crate::metrics;
+use chrono::{DateTime, Utc};
+use config::ConfigError;
+use lapin::{Channel, Connection, ConnectionProperties, Consumer, options::*, types::FieldTable};
+use lapin::auth::SASLMechanism;
 use lapin::message::Delivery;
-use log::{info, error};
-use tokio::sync::mpsc;
+use lapin::tcp::{RustlsConnector, RustlsConnectorConfig, TcpStream};
+use lapin::uri::{AMQPScheme, AMQPUri};
+use log::{info, warn, error};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio_util::sync::CancellationToken;
 use futures_util::StreamExt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+const CONSUMER_TAG_PREFIX: &str = "rabbitmq-kafka-bridge";
+
+/// Default prefetch per processor worker: enough to keep every worker busy while
+/// earlier deliveries wait for Kafka to confirm them.
+const PREFETCH_PER_WORKER: usize = 8;
+
+/// A delivery handed to the processor, stamped with the time the bridge received it.
+pub struct ReceivedDelivery {
+    pub delivery: Delivery,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Connection state of the RabbitMQ consumer, published on every transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
 
 pub struct RabbitMQConsumer {
     config: RabbitMQConfig,
+    uri: AMQPUri,
+    /// TLS connector for `amqps://` URIs.
+    tls: Option<RustlsConnector>,
+    consumer_tag: String,
+    prefetch_count: u16,
+    state: watch::Sender<ConnectionState>,
+    /// Channel currently consuming, so the readiness probe can check it is still open.
+    channel: watch::Sender<Option<Channel>>,
+    /// Connection and channel kept open after shutdown so in-flight deliveries can still be acked.
+    session: Mutex<Option<(Connection, Channel)>>,
 }
 
 impl RabbitMQConsumer {
-    pub fn new(config: RabbitMQConfig) -> Self {
-        Self { config }
+    /// Validate the URI and TLS settings and load the certificates, without connecting yet.
+    pub fn new(config: RabbitMQConfig, instance_id: &str, workers: usize) -> Result<Self> {
+        let (uri, tls) = connection_settings(&config).map_err(|e| {
+            error!("Invalid RabbitMQ connection configuration: {}", e);
+            AppError::ConfigError(e)
+        })?;
+
+        let consumer_tag = config
+            .consumer_tag
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", CONSUMER_TAG_PREFIX, instance_id));
+        let prefetch_count = config
+            .prefetch_count
+            .unwrap_or_else(|| u16::try_from(workers.max(1) * PREFETCH_PER_WORKER).unwrap_or(u16::MAX));
+        info!("RabbitMQ consumer tag: {}, prefetch count: {}", consumer_tag, prefetch_count);
+
+        let (state, _) = watch::channel(ConnectionState::Disconnected);
+        let (channel, _) = watch::channel(None);
+        Ok(Self { config, uri, tls, consumer_tag, prefetch_count, state, channel, session: Mutex::new(None) })
+    }
+
+    /// Subscribe to connection state changes.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Subscribe to the channel currently consuming, if any.
+    pub fn channel(&self) -> watch::Receiver<Option<Channel>> {
+        self.channel.subscribe()
+    }
+
+    /// Consume from the queue until shutdown is requested or the processor goes away,
+    /// reconnecting with exponential backoff whenever the connection is lost or cannot
+    /// be established.
+    pub async fn start(&self, message_sender: mpsc::Sender<ReceivedDelivery>, shutdown: CancellationToken) -> Result<()> {
+        let reconnect = &self.config.reconnect;
+        let mut backoff = Backoff::new(reconnect.initial_backoff_ms, reconnect.max_backoff_ms, reconnect.multiplier, reconnect.jitter);
+
+        loop {
+            self.set_state(ConnectionState::Connecting);
+
+            match self.consume(&message_sender, &mut backoff, &shutdown).await {
+                Ok(()) if shutdown.is_cancelled() => return Ok(()),
+                Ok(()) => warn!("RabbitMQ consumer stream ended"),
+                Err(e) => error!("RabbitMQ consumer error: {}", e),
+            }
+
+            self.channel.send_replace(None);
+            self.set_state(ConnectionState::Disconnected);
+
+            if message_sender.is_closed() {
+                info!("Message processor stopped, no longer consuming from RabbitMQ");
+                return Ok(());
+            }
+
+            let delay = backoff.next_delay();
+            warn!("Reconnecting to RabbitMQ in {:?} (attempt {})", delay, backoff.attempt());
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    /// Close the connection kept open by [`start`](Self::start) after a shutdown.
+    pub async fn close(&self) {
+        if let Some((connection, channel)) = self.session.lock().await.take() {
+            info!("Closing RabbitMQ connection");
+
+            if let Err(e) = channel.close(200, "Bridge shutting down").await {
+                warn!("Failed to close RabbitMQ channel: {}", e);
+            }
+            if let Err(e) = connection.close(200, "Bridge shutting down").await {
+                warn!("Failed to close RabbitMQ connection: {}", e);
+            }
+
+            self.channel.send_replace(None);
+            self.set_state(ConnectionState::Disconnected);
+        }
+    }
+
+    async fn consume(
+        &self,
+        message_sender: &mpsc::Sender<ReceivedDelivery>,
+        backoff: &mut Backoff,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        // Connecting can hang for minutes on an unreachable broker, so give up on shutdown
+        let (connection, channel, mut consumer) = tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => {
+                info!("Shutdown requested while connecting to RabbitMQ");
+                return Ok(());
+            },
+            subscription = self.subscribe() => subscription?,
+        };
+
+        info!("Started consuming from queue: {}", self.config.queue);
+
+        self.channel.send_replace(Some(channel.clone()));
+        self.set_state(ConnectionState::Connected);
+        backoff.reset();
+
+        // Process incoming messages
+        loop {
+            let delivery = tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                delivery = consumer.next() => delivery,
+            };
+
+            let Some(delivery) = delivery else {
+                return Ok(());
+            };
+
+            match delivery {
+                Ok(delivery) => {
+                    info!("Received message from RabbitMQ");
+                    metrics::MESSAGES_CONSUMED.inc();
+
+                    // Send the delivery to the processor
+                    let received = ReceivedDelivery { delivery, received_at: Utc::now() };
+                    if let Err(e) = message_sender.send(received).await {
+                        error!("Failed to send message to processor: {}", e);
+                        return Ok(());
+                    }
+
+                    let depth = message_sender.max_capacity() - message_sender.capacity();
+                    metrics::CHANNEL_DEPTH.set(depth as i64);
+                },
+                Err(e) => {
+                    // lapin drops the consumer after a connection error, so the stream
+                    // would never yield again; reconnect instead
+                    error!("Failed to receive message: {}", e);
+                    return Err(AppError::RabbitMQConnectionError(e));
+                }
+            }
+        }
+
+        self.stop_consuming(&channel, consumer).await;
+        *self.session.lock().await = Some((connection, channel));
+
+        Ok(())
     }
 
-    pub async fn start(&self, message_sender: mpsc::Sender<Delivery>) -> Result<()> {
+    /// Connect, open a channel and start consuming from the configured queue.
+    async fn subscribe(&self) -> Result<(Connection, Channel, Consumer)> {
         info!("Connecting to RabbitMQ at {}", self.config.uri);
-        
+
         // Create a connection to RabbitMQ
-        let connection = Connection::connect(
-            &self.config.uri,
-            ConnectionProperties::default(),
-        ).await.map_err(|e| {
+        let connection = self.connect().await.map_err(|e| {
             error!("Failed to connect to RabbitMQ: {}", e);
             AppError::RabbitMQConnectionError(e)
         })?;
-        
+
         info!("Connected to RabbitMQ");
-        
+
         // Create a channel
         let channel = connection.create_channel().await.map_err(|e| {
             error!("Failed to create channel: {}", e);
             AppError::RabbitMQConnectionError(e)
         })?;
-        
+
         info!("Created channel");
-        
+
+        // Bound the deliveries RabbitMQ pushes ahead of their acknowledgement
+        channel.basic_qos(self.prefetch_count, BasicQosOptions::default()).await.map_err(|e| {
+            error!("Failed to set prefetch count: {}", e);
+            AppError::RabbitMQConnectionError(e)
+        })?;
+
         // Declare the queue to consume from
         let _queue = channel.queue_declare(
             &self.config.queue,
@@ -46,39 +234,141 @@ impl RabbitMQConsumer {
             error!("Failed to declare queue: {}", e);
             AppError::RabbitMQConnectionError(e)
         })?;
-        
+
         info!("Declared queue: {}", self.config.queue);
-        
+
         // Start consuming messages
-        let mut consumer = channel.basic_consume(
+        let consumer = channel.basic_consume(
             &self.config.queue,
-            "rabbitmq-kafka-bridge",
+            &self.consumer_tag,
             BasicConsumeOptions::default(),
             FieldTable::default(),
         ).await.map_err(|e| {
             error!("Failed to start consuming: {}", e);
             AppError::RabbitMQConnectionError(e)
         })?;
-        
-        info!("Started consuming from queue: {}", self.config.queue);
-        
-        // Process incoming messages
-        while let Some(delivery) = consumer.next().await {
-            match delivery {
-                Ok(delivery) => {
-                    info!("Received message from RabbitMQ");
-                    
-                    // Send the delivery to the processor
-                    if let Err(e) = message_sender.send(delivery).await {
-                        error!("Failed to send message to processor: {}", e);
-                    }
-                },
-                Err(e) => {
-                    error!("Failed to receive message: {}", e);
-                }
+
+        Ok((connection, channel, consumer))
+    }
+
+    /// Cancel the AMQP consumer and requeue deliveries that were prefetched but
+    /// never handed to the processor.
+    async fn stop_consuming(&self, channel: &Channel, mut consumer: Consumer) {
+        info!("Cancelling RabbitMQ consumer");
+
+        if let Err(e) = channel.basic_cancel(&self.consumer_tag, BasicCancelOptions::default()).await {
+            error!("Failed to cancel consumer: {}", e);
+            return;
+        }
+
+        while let Some(Ok(delivery)) = consumer.next().await {
+            match delivery.nack(BasicNackOptions { requeue: true, ..Default::default() }).await {
+                Ok(()) => metrics::ACKNOWLEDGEMENTS.with_label_values(&["nack"]).inc(),
+                Err(e) => error!("Failed to requeue message: {}", e),
             }
         }
-        
-        Ok(())
     }
-}
\ No newline at end of file
+
+    // The handshake result type is dictated by lapin
+    #[allow(clippy::result_large_err)]
+    async fn connect(&self) -> lapin::Result<Connection> {
+        let Some(connector) = self.tls.clone() else {
+            return Connection::connect_uri(self.uri.clone(), self.connection_properties()).await;
+        };
+
+        let server_name = self.config.tls.server_name.clone().unwrap_or_else(|| self.uri.authority.host.clone());
+        let connect = move |uri: &AMQPUri| {
+            let address = format!("{}:{}", uri.authority.host, uri.authority.port);
+            let stream = match uri.query.connection_timeout {
+                Some(timeout) => TcpStream::connect_timeout(address, Duration::from_millis(timeout))?,
+                None => TcpStream::connect(address)?,
+            };
+            let stream = stream.into_rustls(&connector, &server_name)?;
+            stream.set_nonblocking(true)?;
+            Ok(stream)
+        };
+
+        Connection::connector(self.uri.clone(), Box::new(connect), self.connection_properties()).await
+    }
+
+    /// Name the connection after the consumer so each replica can be told apart in the management UI.
+    fn connection_properties(&self) -> ConnectionProperties {
+        ConnectionProperties::default().with_connection_name(self.consumer_tag.clone().into())
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous = self.state.send_replace(state);
+        if previous != state {
+            info!("RabbitMQ connection state: {:?} -> {:?}", previous, state);
+        }
+    }
+}
+
+/// Parse the URI, apply the configured auth mechanism, and build the TLS connector
+/// for `amqps://` URIs.
+fn connection_settings(config: &RabbitMQConfig) -> std::result::Result<(AMQPUri, Option<RustlsConnector>), ConfigError> {
+    let mut uri: AMQPUri = config.uri.parse().map_err(|e| invalid(format!("invalid rabbitmq.uri: {}", e)))?;
+
+    match config.auth_mechanism {
+        Some(AmqpAuthMechanism::Plain) => uri.query.auth_mechanism = Some(SASLMechanism::Plain),
+        Some(AmqpAuthMechanism::External) => uri.query.auth_mechanism = Some(SASLMechanism::External),
+        None => {},
+    }
+
+    if uri.query.auth_mechanism == Some(SASLMechanism::External) && config.tls.cert_file.is_none() {
+        return Err(invalid("EXTERNAL authentication requires rabbitmq.tls.cert_file and key_file".to_string()));
+    }
+
+    if uri.scheme == AMQPScheme::AMQP {
+        if config.tls.is_configured() {
+            return Err(invalid("rabbitmq.tls settings require an amqps:// URI".to_string()));
+        }
+        return Ok((uri, None));
+    }
+
+    let connector = tls_connector(&config.tls)?;
+    Ok((uri, Some(connector)))
+}
+
+/// Trust the system CA store plus `ca_file`, and present the client certificate if one is set.
+fn tls_connector(tls: &RabbitMQTlsConfig) -> std::result::Result<RustlsConnector, ConfigError> {
+    let mut roots = RustlsConnectorConfig::new_with_native_certs()
+        .map_err(|e| invalid(format!("cannot load the system CA certificates: {}", e)))?;
+
+    if let Some(ca_file) = &tls.ca_file {
+        let certs = rustls_pemfile::certs(&mut open(ca_file)?)
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(|e| invalid(format!("invalid certificate in {}: {}", ca_file.display(), e)))?;
+        if certs.is_empty() {
+            return Err(invalid(format!("no certificates found in {}", ca_file.display())));
+        }
+        roots.add_parsable_certificates(certs);
+    }
+
+    let (cert_file, key_file) = match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        (None, None) => return Ok(roots.connector_with_no_client_auth()),
+        _ => return Err(invalid("rabbitmq.tls.cert_file and key_file must be set together".to_string())),
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_file)?)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| invalid(format!("invalid certificate in {}: {}", cert_file.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificates found in {}", cert_file.display())));
+    }
+
+    let key = rustls_pemfile::private_key(&mut open(key_file)?)
+        .map_err(|e| invalid(format!("invalid private key in {}: {}", key_file.display(), e)))?
+        .ok_or_else(|| invalid(format!("no private key found in {}", key_file.display())))?;
+
+    roots
+        .connector_with_single_cert(certs, key)
+        .map_err(|e| invalid(format!("invalid client certificate or key: {}", e)))
+}
+
+fn open(path: &Path) -> std::result::Result<BufReader<File>, ConfigError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| invalid(format!("cannot read {}: {}", path.display(), e)))
+}
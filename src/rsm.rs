@@ -0,0 +1,561 @@
+use crate::ais140::{EmergencyPacket, HealthPacket, LoginPacket};
+use crate::config::{CoordinateFormat, PacketKind, RsmChecksum, RsmConfig, UtcOffset};
+use crate::decoder::{self, DecodedRecord, GpsTimeWindow, ProtocolDecoder};
+use crate::error::{self, RsmParseError};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use log::debug;
+use serde_json::json;
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// Minimum number of comma-separated fields in an RSM packet (up to and including HDOP).
+pub const MIN_FIELDS: usize = 20;
+
+/// `date_time` layouts seen across device firmwares, tried in order.
+const DATE_TIME_FORMATS: &[&str] = &[
+    "%d%m%Y%H%M%S",
+    "%d%m%y%H%M%S",
+    "%d/%m/%Y %H:%M:%S",
+    "%d-%m-%Y %H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+];
+
+/// Hemisphere indicator that follows a latitude or longitude value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hemisphere {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Hemisphere {
+    /// Sign to apply to an unsigned coordinate in this hemisphere.
+    pub fn sign(self) -> f64 {
+        match self {
+            Hemisphere::North | Hemisphere::East => 1.0,
+            Hemisphere::South | Hemisphere::West => -1.0,
+        }
+    }
+}
+
+/// A single `$RSM` tracking packet with every field parsed into its proper type.
+///
+/// Fields up to HDOP are mandatory; the trailing status fields are optional because
+/// not every firmware sends them, but when present they must be well formed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RsmPacket {
+    pub header: String,
+    pub vendor_id: String,
+    pub firmware_version: String,
+    pub packet_type: String,
+    pub packet_status: String,
+    pub imei: String,
+    pub vehicle_reg_no: String,
+    pub gps_fix: bool,
+    /// Device-local timestamp; see [`RsmPacket::gps_time`] for the UTC value.
+    pub date_time: NaiveDateTime,
+    pub latitude: f64,
+    pub latitude_dir: Hemisphere,
+    pub longitude: f64,
+    pub longitude_dir: Hemisphere,
+    pub speed: f64,
+    pub heading: f64,
+    pub satellites: u8,
+    pub altitude: f64,
+    pub pdop: f64,
+    pub hdop: f64,
+    pub network_operator: Option<String>,
+    pub ignition: Option<bool>,
+    pub main_power_status: Option<bool>,
+    pub main_input_voltage: Option<f64>,
+    pub internal_battery_voltage: Option<f64>,
+    pub emergency_status: Option<bool>,
+    pub tamper_alert: Option<bool>,
+    pub gsm_signal_strength: Option<u8>,
+    pub mcc: Option<u16>,
+    pub mnc: Option<u16>,
+}
+
+impl RsmPacket {
+    pub fn parse(message: &str) -> Result<Self, RsmParseError> {
+        let fields = Fields::split(message, MIN_FIELDS)?;
+
+        Ok(Self {
+            header: fields.text(0),
+            vendor_id: fields.text(1),
+            firmware_version: fields.text(2),
+            packet_type: fields.text(3),
+            packet_status: fields.text(5),
+            imei: fields.imei(6)?,
+            vehicle_reg_no: fields.text(7),
+            gps_fix: fields.required(8, "gps_fix", parse_gps_fix)?,
+            date_time: fields.date_time(9)?,
+            latitude: fields.required(10, "latitude", parse_f64)?,
+            latitude_dir: fields.required(11, "latitude_dir", parse_latitude_dir)?,
+            longitude: fields.required(12, "longitude", parse_f64)?,
+            longitude_dir: fields.required(13, "longitude_dir", parse_longitude_dir)?,
+            speed: fields.required(14, "speed", parse_f64)?,
+            heading: fields.required(15, "heading", parse_f64)?,
+            satellites: fields.required(16, "satellites", |v| v.parse().ok())?,
+            altitude: fields.required(17, "altitude", parse_f64)?,
+            pdop: fields.required(18, "pdop", parse_f64)?,
+            hdop: fields.required(19, "hdop", parse_f64)?,
+            network_operator: fields.optional(20, "network_operator", |v| Some(v.to_string()))?,
+            ignition: fields.optional(21, "ignition", parse_flag)?,
+            main_power_status: fields.optional(22, "main_power_status", parse_flag)?,
+            main_input_voltage: fields.optional(23, "main_input_voltage", parse_f64)?,
+            internal_battery_voltage: fields.optional(24, "internal_battery_voltage", parse_f64)?,
+            emergency_status: fields.optional(25, "emergency_status", parse_flag)?,
+            tamper_alert: fields.optional(26, "tamper_alert", parse_tamper)?,
+            gsm_signal_strength: fields.optional(27, "gsm_signal_strength", |v| v.parse().ok())?,
+            mcc: fields.optional(28, "mcc", |v| v.parse().ok())?,
+            mnc: fields.optional(29, "mnc", |v| v.parse().ok())?,
+        })
+    }
+
+    /// GPS time in UTC, interpreting `date_time` in the device's configured offset.
+    pub fn gps_time(&self, timezone: UtcOffset) -> DateTime<Utc> {
+        (self.date_time - timezone.0).and_utc()
+    }
+
+    /// Latitude and longitude in signed decimal degrees (negative for S and W),
+    /// rejecting positions outside ±90° / ±180°.
+    pub fn position(&self, format: CoordinateFormat) -> Result<(f64, f64), RsmParseError> {
+        position(format, (self.latitude, self.latitude_dir), (self.longitude, self.longitude_dir))
+    }
+}
+
+/// Convert a latitude/longitude pair to signed decimal degrees (negative for S and W),
+/// rejecting positions outside ±90° / ±180°.
+pub fn position(
+    format: CoordinateFormat,
+    (latitude, latitude_dir): (f64, Hemisphere),
+    (longitude, longitude_dir): (f64, Hemisphere),
+) -> Result<(f64, f64), RsmParseError> {
+    let latitude = to_decimal_degrees("latitude", latitude, format)? * latitude_dir.sign();
+    let longitude = to_decimal_degrees("longitude", longitude, format)? * longitude_dir.sign();
+
+    if latitude.abs() > 90.0 {
+        return Err(RsmParseError::OutOfRange { field: "latitude", value: latitude });
+    }
+    if longitude.abs() > 180.0 {
+        return Err(RsmParseError::OutOfRange { field: "longitude", value: longitude });
+    }
+
+    Ok((latitude, longitude))
+}
+
+fn to_decimal_degrees(field: &'static str, value: f64, format: CoordinateFormat) -> Result<f64, RsmParseError> {
+    match format {
+        CoordinateFormat::DecimalDegrees => Ok(value),
+        CoordinateFormat::DegreesMinutes => {
+            let degrees = (value / 100.0).trunc();
+            let minutes = value - degrees * 100.0;
+
+            if value < 0.0 || minutes >= 60.0 {
+                return Err(RsmParseError::InvalidField { field, value: value.to_string() });
+            }
+
+            Ok(degrees + minutes / 60.0)
+        }
+    }
+}
+
+/// Decoder for `$RSM` vendor packets: tracking packets plus the AIS-140 login,
+/// health and emergency packets, told apart by their packet type code.
+pub struct RsmDecoder {
+    config: RsmConfig,
+    window: GpsTimeWindow,
+}
+
+impl RsmDecoder {
+    pub fn new(config: RsmConfig, window: GpsTimeWindow) -> Self {
+        Self { config, window }
+    }
+
+    /// Normalise a tracking packet into the canonical source fields the output schema maps from.
+    fn tracking_fields(&self, packet: &RsmPacket) -> Result<DecodedRecord, RsmParseError> {
+        // GPS time in UTC (RFC 3339), flagged when implausibly far from now
+        let gps_time = packet.gps_time(self.config.timezone(&packet.vendor_id, &packet.imei));
+        let suspect = self.window.is_suspect(gps_time, &packet.imei);
+
+        // Coordinates in decimal degrees, negative for S and W
+        let format = self.config.coordinate_format(&packet.vendor_id, &packet.imei);
+        let (latitude, longitude) = packet.position(format)?;
+
+        let fields = decoder::object(json!({
+            "header": packet.header,
+            "vendor_id": packet.vendor_id,
+            "firmware_version": packet.firmware_version,
+            "packet_type": packet.packet_type,
+            "packet_status": packet.packet_status,
+            "imei": packet.imei,
+            "vehicle_reg_no": packet.vehicle_reg_no,
+            "gps_fix": packet.gps_fix,
+            "gps_time": gps_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "gps_time_suspect": suspect,
+            "latitude": latitude,
+            "longitude": longitude,
+            "speed": packet.speed,
+            "heading": packet.heading,
+            "satellites": packet.satellites,
+            "altitude": packet.altitude,
+            "pdop": packet.pdop,
+            "hdop": packet.hdop,
+            "network_operator": packet.network_operator,
+            "ignition": packet.ignition,
+            "main_power_status": packet.main_power_status,
+            "main_input_voltage": packet.main_input_voltage,
+            "internal_battery_voltage": packet.internal_battery_voltage,
+            "emergency_status": packet.emergency_status,
+            "tamper_alert": packet.tamper_alert,
+            "gsm_signal_strength": packet.gsm_signal_strength,
+            "mcc": packet.mcc,
+            "mnc": packet.mnc,
+        }));
+
+        Ok(DecodedRecord::new(PacketKind::Tracking, fields))
+    }
+
+    fn login_fields(&self, packet: &LoginPacket) -> Result<DecodedRecord, RsmParseError> {
+        let format = self.config.coordinate_format(&packet.vendor_id, &packet.imei);
+        let (latitude, longitude) = packet.position(format)?;
+
+        let fields = decoder::object(json!({
+            "header": packet.header,
+            "vendor_id": packet.vendor_id,
+            "firmware_version": packet.firmware_version,
+            "packet_type": packet.packet_type,
+            "packet_status": packet.packet_status,
+            "imei": packet.imei,
+            "vehicle_reg_no": packet.vehicle_reg_no,
+            "protocol_version": packet.protocol_version,
+            "latitude": latitude,
+            "longitude": longitude,
+        }));
+
+        Ok(DecodedRecord::new(PacketKind::Login, fields))
+    }
+
+    fn health_fields(&self, packet: &HealthPacket) -> DecodedRecord {
+        let fields = decoder::object(json!({
+            "header": packet.header,
+            "vendor_id": packet.vendor_id,
+            "firmware_version": packet.firmware_version,
+            "packet_type": packet.packet_type,
+            "packet_status": packet.packet_status,
+            "imei": packet.imei,
+            "vehicle_reg_no": packet.vehicle_reg_no,
+            "battery_percentage": packet.battery_percentage,
+            "low_battery_threshold": packet.low_battery_threshold,
+            "memory_percentage": packet.memory_percentage,
+            "data_update_rate_ignition_on": packet.data_update_rate_ignition_on,
+            "data_update_rate_ignition_off": packet.data_update_rate_ignition_off,
+            "digital_io_status": packet.digital_io_status,
+            "analog_io_status": packet.analog_io_status,
+        }));
+
+        DecodedRecord::new(PacketKind::Health, fields)
+    }
+
+    fn emergency_fields(&self, packet: &EmergencyPacket) -> Result<DecodedRecord, RsmParseError> {
+        let gps_time = packet.gps_time(self.config.timezone(&packet.vendor_id, &packet.imei));
+        let suspect = self.window.is_suspect(gps_time, &packet.imei);
+
+        let format = self.config.coordinate_format(&packet.vendor_id, &packet.imei);
+        let (latitude, longitude) = packet.position(format)?;
+
+        let fields = decoder::object(json!({
+            "header": packet.header,
+            "vendor_id": packet.vendor_id,
+            "firmware_version": packet.firmware_version,
+            "packet_type": packet.packet_type,
+            "packet_status": packet.packet_status,
+            "imei": packet.imei,
+            "vehicle_reg_no": packet.vehicle_reg_no,
+            "gps_fix": packet.gps_fix,
+            "gps_time": gps_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "gps_time_suspect": suspect,
+            "latitude": latitude,
+            "longitude": longitude,
+            "altitude": packet.altitude,
+            "speed": packet.speed,
+            "distance": packet.distance,
+            "location_provider": packet.location_provider,
+            "reply_number": packet.reply_number,
+        }));
+
+        Ok(DecodedRecord::new(PacketKind::Emergency, fields))
+    }
+}
+
+impl ProtocolDecoder for RsmDecoder {
+    fn name(&self) -> &'static str {
+        "rsm"
+    }
+
+    fn detect(&self, payload: &[u8]) -> bool {
+        payload.starts_with(b"$RSM")
+    }
+
+    /// Gateways may batch several packets into one message, separated by newlines
+    /// or following one another after the `*` terminator.
+    fn frames<'a>(&self, payload: &'a [u8]) -> Vec<&'a [u8]> {
+        payload
+            .split_inclusive(|byte| matches!(byte, b'\n' | b'*'))
+            .map(<[u8]>::trim_ascii)
+            .filter(|frame| !frame.is_empty())
+            .collect()
+    }
+
+    fn decode(&self, payload: &[u8], _device_id: Option<&str>) -> error::Result<Vec<DecodedRecord>> {
+        let message = String::from_utf8_lossy(payload);
+        let vendor_id = peek_field(&message, 1).unwrap_or_default();
+        let imei = peek_imei(&message).unwrap_or_default();
+        let message = strip_checksum(&message, self.config.checksum(vendor_id, imei))?;
+
+        let kind = self.config.packet_kind(peek_packet_type(message).unwrap_or_default());
+        debug!("Decoding RSM {} packet", kind.name());
+
+        let record = match kind {
+            PacketKind::Tracking => self.tracking_fields(&RsmPacket::parse(message)?)?,
+            PacketKind::Login => self.login_fields(&LoginPacket::parse(message)?)?,
+            PacketKind::Health => self.health_fields(&HealthPacket::parse(message)?),
+            PacketKind::Emergency => self.emergency_fields(&EmergencyPacket::parse(message)?)?,
+        };
+
+        Ok(vec![record])
+    }
+
+    fn peek_field<'a>(&self, payload: &'a [u8], field: &str) -> Option<Cow<'a, str>> {
+        let payload = std::str::from_utf8(payload).ok()?;
+        let value = match field {
+            "imei" => peek_imei(payload),
+            "vehicle_reg_no" => peek_vehicle_reg_no(payload),
+            _ => None,
+        };
+        value.map(Cow::Borrowed)
+    }
+}
+
+/// Verify the checksum field that ends a packet and return the packet without it.
+/// Packets are returned unchanged when the vendor sends no checksum.
+pub fn strip_checksum(message: &str, mode: RsmChecksum) -> Result<&str, RsmParseError> {
+    if mode == RsmChecksum::None {
+        return Ok(message);
+    }
+
+    let message = message.trim();
+    let body = message.split_once('*').map_or(message, |(body, _)| body);
+    let (content, checksum) = body.rsplit_once(',').ok_or(RsmParseError::MissingField { field: "checksum" })?;
+    let checksum = checksum.trim();
+    if checksum.is_empty() {
+        return Err(RsmParseError::MissingField { field: "checksum" });
+    }
+
+    let covered = content.strip_prefix('$').unwrap_or(content).as_bytes();
+    let actual = match mode {
+        RsmChecksum::None | RsmChecksum::Skip => return Ok(content),
+        RsmChecksum::Xor => format!("{:02X}", covered.iter().fold(0u8, |checksum, byte| checksum ^ byte)),
+        RsmChecksum::Crc16 => format!("{:04X}", crc16_ccitt(covered)),
+    };
+
+    if !checksum.eq_ignore_ascii_case(&actual) {
+        return Err(RsmParseError::ChecksumMismatch { expected: checksum.to_string(), actual });
+    }
+
+    Ok(content)
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF).
+fn crc16_ccitt(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFFu16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+/// Extract the IMEI from a raw packet without parsing the remaining fields.
+pub fn peek_imei(message: &str) -> Option<&str> {
+    peek_field(message, 6)
+}
+
+/// Extract the vehicle registration number from a raw packet without parsing the remaining fields.
+pub fn peek_vehicle_reg_no(message: &str) -> Option<&str> {
+    peek_field(message, 7)
+}
+
+/// Extract the packet type code from a raw packet without parsing the remaining fields.
+pub fn peek_packet_type(message: &str) -> Option<&str> {
+    peek_field(message, 3)
+}
+
+fn peek_field(message: &str, index: usize) -> Option<&str> {
+    message.split(',').nth(index).map(str::trim).filter(|value| !value.is_empty())
+}
+
+impl FromStr for RsmPacket {
+    type Err = RsmParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Comma-separated fields of an RSM-framed packet.
+pub struct Fields<'a>(Vec<&'a str>);
+
+impl<'a> Fields<'a> {
+    /// Split a packet into fields, dropping the `*` terminator and anything after it,
+    /// and check the header and the minimum field count.
+    pub fn split(message: &'a str, min_fields: usize) -> Result<Self, RsmParseError> {
+        let message = message.trim();
+        let body = message.split_once('*').map_or(message, |(body, _)| body);
+
+        let parts: Vec<&str> = body.split(',').collect();
+
+        if parts.len() < min_fields {
+            return Err(RsmParseError::InsufficientFields(parts.len()));
+        }
+
+        if !parts[0].starts_with("$RSM") {
+            return Err(RsmParseError::InvalidHeader);
+        }
+
+        Ok(Self::new(parts))
+    }
+
+    /// Wrap fields that have already been split and checked, for other comma-separated
+    /// formats that share these field parsers.
+    pub fn new(parts: Vec<&'a str>) -> Self {
+        Self(parts)
+    }
+
+    pub fn text(&self, index: usize) -> String {
+        self.0[index].trim().to_string()
+    }
+
+    pub fn required<T>(
+        &self,
+        index: usize,
+        field: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, RsmParseError> {
+        let value = self.0[index].trim();
+        if value.is_empty() {
+            return Err(RsmParseError::MissingField { field });
+        }
+        parse(value).ok_or_else(|| RsmParseError::InvalidField {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    pub fn optional<T>(
+        &self,
+        index: usize,
+        field: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Option<T>, RsmParseError> {
+        match self.0.get(index).map(|v| v.trim()) {
+            None | Some("") => Ok(None),
+            Some(_) => self.required(index, field, parse).map(Some),
+        }
+    }
+
+    pub fn date_time(&self, index: usize) -> Result<NaiveDateTime, RsmParseError> {
+        let value = self.0[index].trim();
+        if value.is_empty() {
+            return Err(RsmParseError::MissingField { field: "date_time" });
+        }
+        DATE_TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+            .ok_or_else(|| RsmParseError::InvalidTimestamp(value.to_string()))
+    }
+
+    pub fn imei(&self, index: usize) -> Result<String, RsmParseError> {
+        self.required(index, "imei", |v| {
+            v.chars().all(|c| c.is_ascii_digit()).then(|| v.to_string())
+        })
+    }
+}
+
+pub fn parse_f64(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_gps_fix(value: &str) -> Option<bool> {
+    match value {
+        "1" | "A" => Some(true),
+        "0" | "V" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_tamper(value: &str) -> Option<bool> {
+    // AIS-140 reports the enclosure as `O`pen or `C`losed; some firmwares use 1/0
+    match value {
+        "O" => Some(true),
+        "C" => Some(false),
+        _ => parse_flag(value),
+    }
+}
+
+pub fn parse_latitude_dir(value: &str) -> Option<Hemisphere> {
+    match value {
+        "N" => Some(Hemisphere::North),
+        "S" => Some(Hemisphere::South),
+        _ => None,
+    }
+}
+
+pub fn parse_longitude_dir(value: &str) -> Option<Hemisphere> {
+    match value {
+        "E" => Some(Hemisphere::East),
+        "W" => Some(Hemisphere::West),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_ccitt_matches_check_value() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn strips_valid_checksums() {
+        assert_eq!(strip_checksum("$123456789,29B1*", RsmChecksum::Crc16), Ok("$123456789"));
+        assert_eq!(strip_checksum("$123456789,29b1*", RsmChecksum::Crc16), Ok("$123456789"));
+        assert_eq!(strip_checksum("$AB,03*", RsmChecksum::Xor), Ok("$AB"));
+        assert_eq!(strip_checksum("$AB,FF*", RsmChecksum::Skip), Ok("$AB"));
+        assert_eq!(strip_checksum("$AB,FF*", RsmChecksum::None), Ok("$AB,FF*"));
+    }
+
+    #[test]
+    fn rejects_bad_or_missing_checksums() {
+        assert_eq!(
+            strip_checksum("$123456789,29B2*", RsmChecksum::Crc16),
+            Err(RsmParseError::ChecksumMismatch { expected: "29B2".to_string(), actual: "29B1".to_string() })
+        );
+        assert_eq!(
+            strip_checksum("$AB,*", RsmChecksum::Xor),
+            Err(RsmParseError::MissingField { field: "checksum" })
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::{FieldMapping, FieldType, OutputConfig, PacketKind, UnitConversion};
+use crate::error::MappingError;
+use log::info;
+use serde_json::{Map, Number, Value};
+
+/// Built-in vehicle tracking schema as (output field, canonical source field) pairs.
+const DEFAULT_TRACKING_FIELDS: &[(&str, &str)] = &[
+    ("deviceId", "imei"),
+    ("gpsTime", "gps_time"),
+    ("gpsTimeSuspect", "gps_time_suspect"),
+    ("deviceSpeed", "speed"),
+    ("orientation", "heading"),
+    ("latitude", "latitude"),
+    ("longitude", "longitude"),
+    ("provider", "network_operator"),
+    ("gpsFix", "gps_fix"),
+    ("satellites", "satellites"),
+    ("altitude", "altitude"),
+    ("pdop", "pdop"),
+    ("hdop", "hdop"),
+    ("ignition", "ignition"),
+    ("mainPowerStatus", "main_power_status"),
+    ("mainInputVoltage", "main_input_voltage"),
+    ("internalBatteryVoltage", "internal_battery_voltage"),
+    ("emergencyStatus", "emergency_status"),
+    ("tamperAlert", "tamper_alert"),
+    ("gsmSignalStrength", "gsm_signal_strength"),
+    ("mcc", "mcc"),
+    ("mnc", "mnc"),
+];
+
+/// Built-in login schema.
+const DEFAULT_LOGIN_FIELDS: &[(&str, &str)] = &[
+    ("deviceId", "imei"),
+    ("vehicleRegNo", "vehicle_reg_no"),
+    ("vendorId", "vendor_id"),
+    ("firmwareVersion", "firmware_version"),
+    ("protocolVersion", "protocol_version"),
+    ("latitude", "latitude"),
+    ("longitude", "longitude"),
+];
+
+/// Built-in health monitoring schema.
+const DEFAULT_HEALTH_FIELDS: &[(&str, &str)] = &[
+    ("deviceId", "imei"),
+    ("vendorId", "vendor_id"),
+    ("firmwareVersion", "firmware_version"),
+    ("batteryPercentage", "battery_percentage"),
+    ("lowBatteryThreshold", "low_battery_threshold"),
+    ("memoryPercentage", "memory_percentage"),
+    ("dataUpdateRateIgnitionOn", "data_update_rate_ignition_on"),
+    ("dataUpdateRateIgnitionOff", "data_update_rate_ignition_off"),
+    ("digitalIoStatus", "digital_io_status"),
+    ("analogIoStatus", "analog_io_status"),
+    ("voltageLevel", "voltage_level"),
+    ("gsmSignalStrength", "gsm_signal_strength"),
+    ("ignition", "ignition"),
+];
+
+/// Built-in emergency alert schema.
+const DEFAULT_EMERGENCY_FIELDS: &[(&str, &str)] = &[
+    ("deviceId", "imei"),
+    ("vehicleRegNo", "vehicle_reg_no"),
+    ("alertType", "packet_type"),
+    ("alarmType", "alarm_type"),
+    ("gpsTime", "gps_time"),
+    ("gpsTimeSuspect", "gps_time_suspect"),
+    ("gpsFix", "gps_fix"),
+    ("latitude", "latitude"),
+    ("longitude", "longitude"),
+    ("altitude", "altitude"),
+    ("deviceSpeed", "speed"),
+    ("distance", "distance"),
+    ("locationProvider", "location_provider"),
+    ("replyNumber", "reply_number"),
+];
+
+/// Output schemas for every packet kind.
+pub struct OutputSchemas {
+    tracking: OutputSchema,
+    login: OutputSchema,
+    health: OutputSchema,
+    emergency: OutputSchema,
+}
+
+impl OutputSchemas {
+    pub fn new(config: OutputConfig) -> Self {
+        Self {
+            tracking: OutputSchema::new(PacketKind::Tracking, config.fields),
+            login: OutputSchema::new(PacketKind::Login, config.login),
+            health: OutputSchema::new(PacketKind::Health, config.health),
+            emergency: OutputSchema::new(PacketKind::Emergency, config.emergency),
+        }
+    }
+
+    pub fn get(&self, kind: PacketKind) -> &OutputSchema {
+        match kind {
+            PacketKind::Tracking => &self.tracking,
+            PacketKind::Login => &self.login,
+            PacketKind::Health => &self.health,
+            PacketKind::Emergency => &self.emergency,
+        }
+    }
+}
+
+/// Declarative mapping from canonical source fields to the output JSON.
+pub struct OutputSchema {
+    fields: Vec<FieldMapping>,
+}
+
+impl OutputSchema {
+    pub fn new(kind: PacketKind, fields: Vec<FieldMapping>) -> Self {
+        if fields.is_empty() {
+            info!("Using the built-in {} output schema", kind.name());
+            return Self::builtin(kind);
+        }
+
+        info!("Using a configured {} output schema with {} fields", kind.name(), fields.len());
+        Self { fields }
+    }
+
+    fn builtin(kind: PacketKind) -> Self {
+        let defaults = match kind {
+            PacketKind::Tracking => DEFAULT_TRACKING_FIELDS,
+            PacketKind::Login => DEFAULT_LOGIN_FIELDS,
+            PacketKind::Health => DEFAULT_HEALTH_FIELDS,
+            PacketKind::Emergency => DEFAULT_EMERGENCY_FIELDS,
+        };
+
+        let fields = defaults
+            .iter()
+            .map(|(name, source)| FieldMapping {
+                name: name.to_string(),
+                source: source.to_string(),
+                field_type: None,
+                unit: None,
+                default: None,
+            })
+            .collect();
+
+        Self { fields }
+    }
+
+    pub fn apply(&self, source: &Map<String, Value>) -> Result<Value, MappingError> {
+        let mut output = Map::with_capacity(self.fields.len());
+
+        for mapping in &self.fields {
+            let value = match source.get(&mapping.source).filter(|value| !value.is_null()) {
+                Some(value) => convert(mapping, value.clone())?,
+                None => mapping.default.clone().unwrap_or(Value::Null),
+            };
+            output.insert(mapping.name.clone(), value);
+        }
+
+        Ok(Value::Object(output))
+    }
+}
+
+fn convert(mapping: &FieldMapping, value: Value) -> Result<Value, MappingError> {
+    let error = |value: &Value, target| MappingError::Conversion {
+        source_field: mapping.source.clone(),
+        value: value.to_string(),
+        target,
+    };
+
+    let value = match mapping.unit {
+        Some(unit) => {
+            let number = as_f64(&value).ok_or_else(|| error(&value, "a number"))?;
+            float(number * unit.factor()).ok_or_else(|| error(&value, "a number"))?
+        },
+        None => value,
+    };
+
+    let Some(field_type) = mapping.field_type else {
+        return Ok(value);
+    };
+
+    let converted = match field_type {
+        FieldType::String => match &value {
+            Value::String(_) => Some(value.clone()),
+            other => Some(Value::String(other.to_string())),
+        },
+        FieldType::Integer => match &value {
+            Value::Bool(flag) => Some(Value::from(*flag as i64)),
+            other => as_f64(other).filter(|n| n.is_finite()).map(|n| Value::from(n.round() as i64)),
+        },
+        FieldType::Float => match &value {
+            Value::Bool(_) => None,
+            other => as_f64(other).and_then(float),
+        },
+        FieldType::Boolean => match &value {
+            Value::Bool(_) => Some(value.clone()),
+            Value::Number(n) => n.as_f64().map(|n| Value::Bool(n != 0.0)),
+            Value::String(s) => match s.to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Some(Value::Bool(true)),
+                "false" | "0" | "no" | "off" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            _ => None,
+        },
+    };
+
+    converted.ok_or_else(|| error(&value, field_type.name()))
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn float(value: f64) -> Option<Value> {
+    Number::from_f64(value).map(Value::Number)
+}
+
+impl UnitConversion {
+    fn factor(self) -> f64 {
+        match self {
+            UnitConversion::KmhToMph => 0.621_371,
+            UnitConversion::MphToKmh => 1.609_344,
+            UnitConversion::KmhToKnots => 0.539_957,
+            UnitConversion::KnotsToKmh => 1.852,
+            UnitConversion::MetersToFeet => 3.280_84,
+            UnitConversion::MillivoltsToVolts => 0.001,
+            UnitConversion::VoltsToMillivolts => 1000.0,
+        }
+    }
+}
+
+impl FieldType {
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::String => "a string",
+            FieldType::Integer => "an integer",
+            FieldType::Float => "a float",
+            FieldType::Boolean => "a boolean",
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use crate::config::PacketKind;
+use crate::decoder::{self, ByteReader, DecodedRecord, GpsTimeWindow, ProtocolDecoder};
+use crate::error::{self, TeltonikaParseError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Value, json};
+use std::borrow::Cow;
+
+const PREAMBLE: [u8; 4] = [0; 4];
+const CODEC_8: u8 = 0x08;
+const CODEC_8_EXTENDED: u8 = 0x8E;
+
+/// Teltonika AVL data codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Codec8,
+    Codec8Extended,
+}
+
+impl Codec {
+    fn from_id(id: u8) -> Result<Self, TeltonikaParseError> {
+        match id {
+            CODEC_8 => Ok(Codec::Codec8),
+            CODEC_8_EXTENDED => Ok(Codec::Codec8Extended),
+            other => Err(TeltonikaParseError::UnsupportedCodec(other)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Codec8 => "codec8",
+            Codec::Codec8Extended => "codec8e",
+        }
+    }
+}
+
+/// A Codec 8 / 8E AVL data packet, as sent over TCP.
+///
+/// Gateways may forward the packet on its own or prefixed with the IMEI handshake
+/// the device sends when it connects (two-byte length followed by the ASCII IMEI).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvlPacket {
+    pub imei: Option<String>,
+    pub codec: Codec,
+    pub records: Vec<AvlRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvlRecord {
+    pub timestamp: DateTime<Utc>,
+    pub priority: u8,
+    /// Decimal degrees, negative for W.
+    pub longitude: f64,
+    /// Decimal degrees, negative for S.
+    pub latitude: f64,
+    /// Metres above sea level.
+    pub altitude: i16,
+    /// Heading in degrees from north.
+    pub angle: u16,
+    pub satellites: u8,
+    /// Speed in km/h.
+    pub speed: u16,
+    /// IO element that triggered the record, or 0 for periodic records.
+    pub event_io_id: u16,
+    pub io: Vec<IoElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IoElement {
+    pub id: u16,
+    pub value: IoValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IoValue {
+    /// Fixed-size value of 1, 2, 4 or 8 bytes.
+    Unsigned(u64),
+    /// Variable-length value (Codec 8E only).
+    Bytes(Vec<u8>),
+}
+
+impl AvlPacket {
+    pub fn parse(payload: &[u8]) -> Result<Self, TeltonikaParseError> {
+        let (imei, payload) = match split_imei(payload) {
+            Some((imei, rest)) => (Some(imei.to_string()), rest),
+            None => (None, payload),
+        };
+
+        let mut reader = ByteReader::new(payload);
+
+        if reader.bytes(PREAMBLE.len()).ok_or(TeltonikaParseError::Truncated("preamble"))? != PREAMBLE {
+            return Err(TeltonikaParseError::InvalidPreamble);
+        }
+
+        // The data field runs from the codec ID to the second record count and is followed by the CRC
+        let declared = reader.u32().ok_or(TeltonikaParseError::Truncated("data field length"))? as usize;
+        if reader.remaining() != declared.saturating_add(4) {
+            return Err(TeltonikaParseError::LengthMismatch { declared, actual: reader.remaining().saturating_sub(4) });
+        }
+
+        let data = &payload[reader.position()..reader.position() + declared];
+        let crc = crc16(data);
+
+        let codec = Codec::from_id(read(reader.u8(), "codec ID")?)?;
+        let count = read(reader.u8(), "record count")?;
+        let records = (0..count)
+            .map(|_| AvlRecord::parse(&mut reader, codec))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let trailer = read(reader.u8(), "record count")?;
+        if trailer != count {
+            return Err(TeltonikaParseError::RecordCountMismatch { header: count, trailer });
+        }
+
+        let expected = read(reader.u32(), "CRC")?;
+        if expected != crc as u32 {
+            return Err(TeltonikaParseError::ChecksumMismatch { expected, actual: crc });
+        }
+
+        Ok(Self { imei, codec, records })
+    }
+}
+
+impl AvlRecord {
+    fn parse(reader: &mut ByteReader, codec: Codec) -> Result<Self, TeltonikaParseError> {
+        let millis = read(reader.u64(), "timestamp")?;
+        let timestamp = i64::try_from(millis)
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .ok_or(TeltonikaParseError::InvalidTimestamp(millis))?;
+
+        let priority = read(reader.u8(), "priority")?;
+        let longitude = read(reader.u32(), "longitude")? as i32 as f64 / 10_000_000.0;
+        let latitude = read(reader.u32(), "latitude")? as i32 as f64 / 10_000_000.0;
+        let altitude = read(reader.u16(), "altitude")? as i16;
+        let angle = read(reader.u16(), "angle")?;
+        let satellites = read(reader.u8(), "satellites")?;
+        let speed = read(reader.u16(), "speed")?;
+
+        if latitude.abs() > 90.0 {
+            return Err(TeltonikaParseError::OutOfRange { field: "latitude", value: latitude });
+        }
+        if longitude.abs() > 180.0 {
+            return Err(TeltonikaParseError::OutOfRange { field: "longitude", value: longitude });
+        }
+
+        // Codec 8E widens IO IDs and counts to two bytes and adds variable-length values
+        let id = |reader: &mut ByteReader| match codec {
+            Codec::Codec8 => reader.u8().map(u16::from),
+            Codec::Codec8Extended => reader.u16(),
+        };
+
+        let event_io_id = read(id(reader), "event IO ID")?;
+        read(id(reader), "IO count")?;
+
+        let mut io = Vec::new();
+        for size in [1usize, 2, 4, 8] {
+            let count = read(id(reader), "IO count")?;
+            for _ in 0..count {
+                let id = read(id(reader), "IO ID")?;
+                let bytes = read(reader.bytes(size), "IO value")?;
+                let value = bytes.iter().fold(0u64, |value, byte| value << 8 | *byte as u64);
+                io.push(IoElement { id, value: IoValue::Unsigned(value) });
+            }
+        }
+
+        if codec == Codec::Codec8Extended {
+            let count = read(reader.u16(), "IO count")?;
+            for _ in 0..count {
+                let id = read(reader.u16(), "IO ID")?;
+                let len = read(reader.u16(), "IO value length")?;
+                let bytes = read(reader.bytes(len as usize), "IO value")?;
+                io.push(IoElement { id, value: IoValue::Bytes(bytes.to_vec()) });
+            }
+        }
+
+        Ok(Self { timestamp, priority, longitude, latitude, altitude, angle, satellites, speed, event_io_id, io })
+    }
+
+    /// Devices that have not had a fix since boot report 0,0 with no satellites.
+    fn has_position(&self) -> bool {
+        self.satellites > 0 || self.latitude != 0.0 || self.longitude != 0.0
+    }
+
+    fn unsigned(&self, id: u16) -> Option<u64> {
+        self.io.iter().find(|element| element.id == id).and_then(|element| match element.value {
+            IoValue::Unsigned(value) => Some(value),
+            IoValue::Bytes(_) => None,
+        })
+    }
+}
+
+/// Decoder for Teltonika Codec 8 and Codec 8 Extended AVL packets.
+pub struct TeltonikaDecoder {
+    window: GpsTimeWindow,
+}
+
+impl TeltonikaDecoder {
+    pub fn new(window: GpsTimeWindow) -> Self {
+        Self { window }
+    }
+
+    /// Normalise an AVL record into the canonical tracking source fields. Well-known
+    /// IO elements are mapped to their tracking fields; every IO element is also
+    /// exposed as `io_<id>` for custom output schemas.
+    fn source_fields(&self, packet: &AvlPacket, record: &AvlRecord, imei: Option<&str>) -> DecodedRecord {
+        let suspect = self.window.is_suspect(record.timestamp, imei.unwrap_or("unknown"));
+
+        // Active GSM operator code, e.g. 40445 for MCC 404 / MNC 45
+        let operator = record.unsigned(241).map(|code| code.to_string()).filter(|code| code.len() > 3);
+        let position = record.has_position().then_some((record.latitude, record.longitude));
+
+        let mut fields = decoder::object(json!({
+            "header": "teltonika",
+            "vendor_id": "teltonika",
+            "packet_type": packet.codec.name(),
+            "imei": imei,
+            "gps_fix": record.satellites > 0,
+            "gps_time": record.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "gps_time_suspect": suspect,
+            "latitude": position.map(|(latitude, _)| latitude),
+            "longitude": position.map(|(_, longitude)| longitude),
+            "speed": record.speed,
+            "heading": record.angle,
+            "satellites": record.satellites,
+            "altitude": record.altitude,
+            "pdop": record.unsigned(181).map(|pdop| pdop as f64 / 10.0),
+            "hdop": record.unsigned(182).map(|hdop| hdop as f64 / 10.0),
+            "ignition": record.unsigned(239).map(|ignition| ignition != 0),
+            "main_power_status": record.unsigned(252).map(|unplugged| unplugged == 0),
+            "main_input_voltage": record.unsigned(66).map(|millivolts| millivolts as f64 / 1000.0),
+            "internal_battery_voltage": record.unsigned(67).map(|millivolts| millivolts as f64 / 1000.0),
+            "emergency_status": record.unsigned(236).map(|alarm| alarm != 0),
+            "gsm_signal_strength": record.unsigned(21),
+            "mcc": operator.as_ref().and_then(|code| code[..3].parse::<u16>().ok()),
+            "mnc": operator.as_ref().and_then(|code| code[3..].parse::<u16>().ok()),
+            "priority": record.priority,
+            "event_io_id": record.event_io_id,
+        }));
+
+        fields.extend(record.io.iter().map(|element| {
+            let value = match &element.value {
+                IoValue::Unsigned(value) => Value::from(*value),
+                IoValue::Bytes(bytes) => Value::String(hex(bytes)),
+            };
+            (format!("io_{}", element.id), value)
+        }));
+
+        DecodedRecord::new(PacketKind::Tracking, fields)
+    }
+}
+
+impl ProtocolDecoder for TeltonikaDecoder {
+    fn name(&self) -> &'static str {
+        "teltonika"
+    }
+
+    fn detect(&self, payload: &[u8]) -> bool {
+        let payload = split_imei(payload).map_or(payload, |(_, rest)| rest);
+        payload.len() > 8
+            && payload.starts_with(&PREAMBLE)
+            && matches!(payload[8], CODEC_8 | CODEC_8_EXTENDED)
+    }
+
+    fn decode(&self, payload: &[u8], device_id: Option<&str>) -> error::Result<Vec<DecodedRecord>> {
+        let packet = AvlPacket::parse(payload)?;
+        let imei = packet.imei.as_deref().or(device_id);
+
+        Ok(packet
+            .records
+            .iter()
+            .map(|record| self.source_fields(&packet, record, imei))
+            .collect())
+    }
+
+    fn peek_field<'a>(&self, payload: &'a [u8], field: &str) -> Option<Cow<'a, str>> {
+        match field {
+            "imei" => split_imei(payload).map(|(imei, _)| Cow::Borrowed(imei)),
+            _ => None,
+        }
+    }
+}
+
+/// Split off a leading IMEI handshake (two-byte length followed by ASCII digits).
+fn split_imei(payload: &[u8]) -> Option<(&str, &[u8])> {
+    let mut reader = ByteReader::new(payload);
+    let len = reader.u16()? as usize;
+    if !(15..=17).contains(&len) {
+        return None;
+    }
+
+    let imei = reader.bytes(len)?;
+    if !imei.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    Some((std::str::from_utf8(imei).ok()?, &payload[2 + len..]))
+}
+
+/// CRC-16/IBM (polynomial 0xA001, reflected, initial value 0) as used by Teltonika.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u16, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 }
+        })
+    })
+}
+
+fn read<T>(value: Option<T>, field: &'static str) -> Result<T, TeltonikaParseError> {
+    value.ok_or(TeltonikaParseError::Truncated(field))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Codec 8 example from the Teltonika protocol documentation: one record with no GPS fix.
+    const CODEC_8_SAMPLE: &str = "000000000000003608010000016B40D8EA30010000000000000000000000000000000105021503010101425E0F01F10000601A014E0000000000000000010000C7CF";
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn crc16_matches_published_sample() {
+        let payload = unhex(CODEC_8_SAMPLE);
+        assert_eq!(crc16(&payload[8..payload.len() - 4]), 0xC7CF);
+    }
+
+    #[test]
+    fn parses_published_sample() {
+        let packet = AvlPacket::parse(&unhex(CODEC_8_SAMPLE)).unwrap();
+        assert_eq!(packet.codec, Codec::Codec8);
+        assert_eq!(packet.records.len(), 1);
+        assert_eq!(packet.records[0].timestamp.timestamp_millis(), 0x0000016B40D8EA30);
+        assert_eq!(packet.records[0].priority, 1);
+        assert!(!packet.records[0].has_position());
+    }
+
+    #[test]
+    fn rejects_corrupted_crc() {
+        let mut payload = unhex(CODEC_8_SAMPLE);
+        *payload.last_mut().unwrap() ^= 0x01;
+        assert_eq!(
+            AvlPacket::parse(&payload),
+            Err(TeltonikaParseError::ChecksumMismatch { expected: 0xC7CE, actual: 0xC7CF })
+        );
+    }
+
+    #[test]
+    fn forwards_no_fix_record_without_position() {
+        let decoder = TeltonikaDecoder::new(GpsTimeWindow::new(&Default::default()));
+        let records = decoder.decode(&unhex(CODEC_8_SAMPLE), Some("356307042441013")).unwrap();
+        assert_eq!(records[0].fields["gps_fix"], false);
+        assert!(records[0].fields["latitude"].is_null());
+        assert!(records[0].fields["longitude"].is_null());
+    }
+}